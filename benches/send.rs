@@ -0,0 +1,356 @@
+use std::{
+	collections::VecDeque,
+	hint::black_box,
+	io::{self, Read, Write},
+	thread,
+	time::Duration,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serial_ws2812::{Config, SerialWs2812};
+use serial_ws2812_shared::{
+	parse_command,
+	parse_data,
+	Command,
+	DataStep,
+	ParseStep,
+	DEVICE_ERROR_MESSAGE,
+	DEVICE_INIT_MESSAGE,
+	DEVICE_OK_MESSAGE,
+	DEVICE_PARTIAL_MESSAGE,
+	MAX_BUFFER_SIZE,
+	MESSAGE_TYPE_LEN,
+	STREAM_END_MESSAGE,
+	STREAM_FRAME_MESSAGE,
+	STREAM_MARKER_LEN,
+};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+/// The size of the USB packets of the device.
+const PACKET_LEN: usize = 64;
+
+/// How long a response takes to reach the host, USB full speed polls the device once per frame.
+const USB_LATENCY: Duration = Duration::from_millis(1);
+
+/// Serial port that answers like the firmware would, without any actual I/O.
+///
+/// Queries are rejected, so the controller falls back to the legacy capabilities.
+struct FakeDevice {
+	responses: VecDeque<u8>,
+	received:  Vec<u8>,
+	command:   Option<Command>,
+
+	strips: usize,
+	leds:   usize,
+
+	/// The ack interval and the frames since the last ack while streaming.
+	stream:  Option<(usize, usize)>,
+	/// Added to every read of a response.
+	latency: Duration,
+}
+
+impl FakeDevice {
+	fn new() -> Self {
+		Self {
+			responses: VecDeque::from(DEVICE_INIT_MESSAGE.to_vec()),
+			received:  Vec::with_capacity(MESSAGE_TYPE_LEN + MAX_BUFFER_SIZE),
+			command:   None,
+
+			strips: 0,
+			leds:   0,
+
+			stream:  None,
+			latency: Duration::ZERO,
+		}
+	}
+
+	/// Takes `latency` to respond, like a device behind a real USB connection.
+	fn with_latency(latency: Duration) -> Self {
+		Self {
+			latency,
+			..Self::new()
+		}
+	}
+
+	/// Handles a streamed frame or the end of the stream, returns `false` once more bytes are needed.
+	fn step_stream(&mut self, ack_interval: usize, frames: usize) -> bool {
+		let Some(marker) = self.received.get(..STREAM_MARKER_LEN) else {
+			return false;
+		};
+
+		if marker == STREAM_END_MESSAGE {
+			self.responses.extend(DEVICE_OK_MESSAGE);
+			self.stream = None;
+			self.received.drain(..STREAM_MARKER_LEN);
+			return true;
+		}
+		if marker != STREAM_FRAME_MESSAGE {
+			self.responses.extend(DEVICE_ERROR_MESSAGE);
+			self.stream = None;
+			self.received.clear();
+			return false;
+		}
+
+		let frame_len = STREAM_MARKER_LEN + 3 * self.strips * self.leds;
+		if self.received.len() < frame_len {
+			return false;
+		}
+		self.received.drain(..frame_len);
+
+		let frames = frames + 1;
+		if frames == ack_interval {
+			self.responses.extend(DEVICE_OK_MESSAGE);
+			self.stream = Some((ack_interval, 0));
+		} else {
+			self.stream = Some((ack_interval, frames));
+		}
+		true
+	}
+
+	/// Handles the received bytes, returns `false` once more bytes are needed.
+	fn step(&mut self) -> bool {
+		if let Some((ack_interval, frames)) = self.stream {
+			return self.step_stream(ack_interval, frames);
+		}
+
+		let command = match self.command {
+			Some(command) => command,
+			None => match parse_command(&self.received) {
+				ParseStep::NeedMore => return false,
+				ParseStep::Command(command) if !command.is_query() => {
+					self.responses.extend(DEVICE_PARTIAL_MESSAGE);
+					self.command = Some(command);
+					command
+				}
+				ParseStep::Command(_) | ParseStep::Error => {
+					self.responses.extend(DEVICE_ERROR_MESSAGE);
+					self.received
+						.drain(..MESSAGE_TYPE_LEN.min(self.received.len()));
+					return true;
+				}
+			},
+		};
+
+		let data = &self.received[MESSAGE_TYPE_LEN..];
+		let len = match parse_data(command, data, self.strips * self.leds) {
+			DataStep::NeedMore => return false,
+			DataStep::Error => {
+				self.responses.extend(DEVICE_ERROR_MESSAGE);
+				0
+			}
+			DataStep::Complete(data) => {
+				let value = || u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+				match command {
+					Command::SetStrips => self.strips = value(),
+					Command::SetLeds => self.leds = value(),
+					Command::Stream => self.stream = Some((value().max(1), 0)),
+					_ => {}
+				}
+
+				self.responses.extend(DEVICE_OK_MESSAGE);
+				data.len()
+			}
+		};
+
+		self.received.drain(..MESSAGE_TYPE_LEN + len);
+		self.command = None;
+		true
+	}
+}
+
+impl Read for FakeDevice {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.responses.is_empty() {
+			return Err(io::ErrorKind::TimedOut.into());
+		}
+
+		thread::sleep(self.latency);
+
+		let len = buf.len().min(self.responses.len());
+		for (byte, response) in buf.iter_mut().zip(self.responses.drain(..len)) {
+			*byte = response;
+		}
+
+		Ok(len)
+	}
+}
+
+impl Write for FakeDevice {
+	/// Takes at most one USB packet per write like the device, so frames are split up the same way.
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let len = buf.len().min(PACKET_LEN);
+		self.received.extend_from_slice(&buf[..len]);
+		while self.step() {}
+
+		Ok(len)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl SerialPort for FakeDevice {
+	fn name(&self) -> Option<String> {
+		Some("fake".to_string())
+	}
+
+	fn baud_rate(&self) -> serialport::Result<u32> {
+		Ok(921_600)
+	}
+
+	fn data_bits(&self) -> serialport::Result<DataBits> {
+		Ok(DataBits::Eight)
+	}
+
+	fn flow_control(&self) -> serialport::Result<FlowControl> {
+		Ok(FlowControl::None)
+	}
+
+	fn parity(&self) -> serialport::Result<Parity> {
+		Ok(Parity::None)
+	}
+
+	fn stop_bits(&self) -> serialport::Result<StopBits> {
+		Ok(StopBits::One)
+	}
+
+	fn timeout(&self) -> Duration {
+		Duration::ZERO
+	}
+
+	fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn set_flow_control(&mut self, _: FlowControl) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn set_parity(&mut self, _: Parity) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn set_timeout(&mut self, _: Duration) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+		Ok(true)
+	}
+
+	fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+		Ok(true)
+	}
+
+	fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+		Ok(false)
+	}
+
+	fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+		Ok(true)
+	}
+
+	fn bytes_to_read(&self) -> serialport::Result<u32> {
+		Ok(self.responses.len() as u32)
+	}
+
+	fn bytes_to_write(&self) -> serialport::Result<u32> {
+		Ok(0)
+	}
+
+	fn clear(&self, _: ClearBuffer) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+		Err(serialport::Error::new(
+			serialport::ErrorKind::Unknown,
+			"the fake device can't be cloned",
+		))
+	}
+
+	fn set_break(&self) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn clear_break(&self) -> serialport::Result<()> {
+		Ok(())
+	}
+}
+
+fn send_leds(c: &mut Criterion) {
+	let mut group = c.benchmark_group("send_leds");
+	// every iteration sends one frame, so the reported throughput is in frames per second
+	group.throughput(Throughput::Elements(1));
+
+	for (strips, leds) in [(8, 512), (4, 256)] {
+		let config = Config::strips(strips, leds);
+		let mut controller = SerialWs2812::from_port(Box::new(FakeDevice::new()), config);
+		controller
+			.configure()
+			.expect("failed to configure fake device");
+
+		let frame = vec![0x55; controller.transfer_size()];
+
+		group.bench_with_input(
+			BenchmarkId::from_parameter(format!("{strips}x{leds}")),
+			&frame,
+			|b, frame| {
+				b.iter(|| controller.send_leds(black_box(frame)).unwrap());
+			},
+		);
+	}
+
+	group.finish();
+}
+
+/// Frames per second with every frame acknowledged against streaming, with the round trips of a
+/// real USB connection where they make the difference.
+fn stream(c: &mut Criterion) {
+	const ACK_INTERVAL: u32 = 8;
+
+	let mut group = c.benchmark_group("stream");
+	group.throughput(Throughput::Elements(1));
+
+	let config = Config::strips(8, 512);
+	let mut controller =
+		SerialWs2812::from_port(Box::new(FakeDevice::with_latency(USB_LATENCY)), config);
+	controller
+		.configure()
+		.expect("failed to configure fake device");
+	let frame = vec![0x55; controller.transfer_size()];
+
+	group.bench_function("per_frame", |b| {
+		b.iter(|| controller.send_leds(black_box(&frame)).unwrap());
+	});
+
+	controller
+		.begin_stream(ACK_INTERVAL)
+		.expect("failed to start streaming");
+	group.bench_function("streamed", |b| {
+		b.iter(|| controller.send_frame_streamed(black_box(&frame)).unwrap());
+	});
+	controller.end_stream().expect("failed to end streaming");
+
+	group.finish();
+}
+
+criterion_group!(benches, send_leds, stream);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use serial_ws2812_shared::{compress_byte, BYTES_PER_LED, MAX_LEDS_PER_STRIP, MAX_STRIPS};
+
+/// The bytes the LED task transposes for a full frame, one byte per strip at a time.
+const FRAME_BYTES: usize = BYTES_PER_LED * MAX_LEDS_PER_STRIP * MAX_STRIPS;
+
+/// Transposes 8 bytes into `out`, like [`compress_byte`].
+type Compress = fn(&[u8; 8], &mut [u8]);
+
+/// The transpose one bit at a time, as the firmware did before [`compress_byte`].
+fn compress_byte_per_bit(i: &[u8; 8], out: &mut [u8]) {
+	for (n, byte) in out[..8].iter_mut().enumerate() {
+		*byte = 0;
+		for (k, input) in i.iter().enumerate() {
+			*byte |= ((input >> (7 - n)) & 1) << k;
+		}
+	}
+}
+
+fn transpose(c: &mut Criterion) {
+	let mut group = c.benchmark_group("transpose");
+	// every iteration transposes a full frame of the largest config
+	group.throughput(Throughput::Bytes(FRAME_BYTES as u64));
+
+	let input: Vec<u8> = (0..FRAME_BYTES).map(|i| (i * 37 % 256) as u8).collect();
+	let mut out = vec![0; FRAME_BYTES];
+
+	let compress: [(&str, Compress); 2] = [
+		("compress_byte", compress_byte),
+		("per_bit", compress_byte_per_bit),
+	];
+	for (name, compress) in compress {
+		group.bench_function(name, |b| {
+			b.iter(|| {
+				for (current, out) in input.chunks_exact(8).zip(out.chunks_exact_mut(8)) {
+					compress(black_box(current.try_into().unwrap()), out);
+				}
+			});
+		});
+	}
+
+	group.finish();
+}
+
+criterion_group!(benches, transpose);
+criterion_main!(benches);
@@ -18,4 +18,5 @@ fn main() {
 
 	println!("cargo:rerun-if-changed=memory.x");
 	println!("cargo:rerun-if-changed=build.rs");
+	println!("cargo:rerun-if-env-changed=SERIAL_WS2812_MAX_POWER_MA");
 }
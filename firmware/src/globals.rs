@@ -1,9 +1,32 @@
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
-use serial_ws2812_shared::{BYTES_PER_LED, MAX_LEDS_PER_STRIP, MAX_STRIPS};
+use core::sync::atomic::AtomicU32;
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal};
+use serial_ws2812_shared::{BYTES_PER_LED, LED_TIMING_WS2812, MAX_LEDS_PER_STRIP, MAX_STRIPS, MIN_RESET_DURATION_US};
 
 pub type LEDs = [[[u8; BYTES_PER_LED]; MAX_LEDS_PER_STRIP]; MAX_STRIPS];
 
-pub type DisplayCommand = (usize, &'static mut LEDs);
+pub struct DisplayCommand {
+	/// The amount of LEDs to drive on every strip, strips with a length of 0 are kept dark.
+	pub lengths:      [usize; MAX_STRIPS],
+	/// Bitmask of the enabled strips, disabled strips are sent zeros.
+	pub enabled:      u32,
+	/// The `COLOR_ORDER_*` value of every strip.
+	pub color_orders: [u8; MAX_STRIPS],
+	pub buffer:       &'static mut LEDs,
+}
 
 pub static DISPLAY_CHANNEL: Channel<CriticalSectionRawMutex, DisplayCommand, 1> = Channel::new();
-pub static RETURN_CHANNEL: Channel<CriticalSectionRawMutex, &'static mut LEDs, 1> = Channel::new();
+/// Holds the buffers that are free to be filled, there are two so the next frame can be received
+/// while the current one is being written to the LEDs.
+pub static RETURN_CHANNEL: Channel<CriticalSectionRawMutex, &'static mut LEDs, 2> = Channel::new();
+
+/// How long the outputs are kept low after a frame, in microseconds.
+pub static RESET_DURATION_US: AtomicU32 = AtomicU32::new(MIN_RESET_DURATION_US);
+
+/// The `LED_TIMING_*` profile of the outputs.
+pub static LED_TIMING: AtomicU32 = AtomicU32::new(LED_TIMING_WS2812);
+
+/// Counts the frames that were written to the LEDs and latched by the reset period.
+pub static FRAMES_SHOWN: AtomicU32 = AtomicU32::new(0);
+/// Signaled every time [`FRAMES_SHOWN`] is incremented.
+pub static FRAME_SHOWN_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
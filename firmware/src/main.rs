@@ -5,6 +5,9 @@
 
 mod globals;
 mod serial;
+mod telemetry;
+mod vendor;
+mod watchdog;
 mod ws2812;
 
 extern crate defmt_rtt;
@@ -16,27 +19,31 @@ use bytemuck::cast;
 use defmt::*;
 use embassy_executor::Executor;
 use embassy_rp::{
+	adc::{Adc, Channel as AdcChannel, Config as AdcConfig, InterruptHandler as AdcInterruptHandler},
 	bind_interrupts,
-	clocks::PllConfig,
 	config::Config,
 	flash::Blocking,
+	gpio::Pull,
 	multicore::{spawn_core1, Stack},
 	peripherals::{PIO0, USB},
 	pio::InterruptHandler as PioInterruptHandler,
 	usb::{Driver, InterruptHandler as UsbInterruptHandler},
 };
-use serial_ws2812_shared::MAX_BUFFER_SIZE;
+use serial_ws2812_shared::{DEVICE_ID_LEN, MAX_BUFFER_SIZE};
 use static_cell::StaticCell;
 
 use crate::{
 	globals::{LEDs, RETURN_CHANNEL},
 	serial::usb_serial_task,
-	ws2812::parallel_led_task,
+	telemetry::telemetry_task,
+	watchdog::{heartbeat_task, watchdog_task},
+	ws2812::{parallel_led_task, OutputPins},
 };
 
 bind_interrupts!(struct Irqs {
 	USBCTRL_IRQ => UsbInterruptHandler<USB>;
 	PIO0_IRQ_0 => PioInterruptHandler<PIO0>;
+	ADC_IRQ_FIFO => AdcInterruptHandler;
 });
 
 const FLASH_JEDEC_BYTES: usize = size_of::<u32>();
@@ -44,22 +51,29 @@ const FLASH_ID_BYTES: usize = 16;
 const ID_BYTES: usize = FLASH_JEDEC_BYTES + FLASH_ID_BYTES;
 const FLASH_SIZE: usize = 2 * 1024 * 1024;
 
+const _: () = assert!(ID_BYTES == DEVICE_ID_LEN);
+
 static mut CORE1_STACK: Stack<4096> = Stack::new();
 static EXECUTOR0: StaticCell<Executor> = StaticCell::new();
 static EXECUTOR1: StaticCell<Executor> = StaticCell::new();
 
 #[cortex_m_rt::entry]
 fn main() -> ! {
+	#[allow(unused_mut)]
 	let mut config = Config::default();
-	let xosc = config.clocks.xosc.as_mut().expect("this should have been configured");
-
-	// overclock to 266Mhz
-	xosc.sys_pll = Some(PllConfig {
-		refdiv:    1,
-		fbdiv:     133,
-		post_div1: 6,
-		post_div2: 1,
-	});
+
+	// overclock to 266Mhz, the PIO clock divider is derived from the actual clock so the LED timing
+	// stays the same either way
+	#[cfg(not(feature = "no-overclock"))]
+	{
+		let xosc = config.clocks.xosc.as_mut().expect("this should have been configured");
+		xosc.sys_pll = Some(embassy_rp::clocks::PllConfig {
+			refdiv:    1,
+			fbdiv:     133,
+			post_div1: 6,
+			post_div2: 1,
+		});
+	}
 
 	let p = embassy_rp::init(config);
 
@@ -71,19 +85,38 @@ fn main() -> ! {
 	id[0..FLASH_JEDEC_BYTES].copy_from_slice(&jedec.to_ne_bytes());
 	flash.blocking_unique_id(&mut id[FLASH_JEDEC_BYTES..]).unwrap();
 
-	let outputs = (p.PIN_0, p.PIN_1, p.PIN_2, p.PIN_3, p.PIN_4, p.PIN_5, p.PIN_6, p.PIN_7);
+	#[cfg(not(any(feature = "pins-8-15", feature = "pins-14-21")))]
+	let outputs: OutputPins = (p.PIN_0, p.PIN_1, p.PIN_2, p.PIN_3, p.PIN_4, p.PIN_5, p.PIN_6, p.PIN_7);
+	#[cfg(feature = "pins-8-15")]
+	let outputs: OutputPins = (
+		p.PIN_8, p.PIN_9, p.PIN_10, p.PIN_11, p.PIN_12, p.PIN_13, p.PIN_14, p.PIN_15,
+	);
+	#[cfg(feature = "pins-14-21")]
+	let outputs: OutputPins = (
+		p.PIN_14, p.PIN_15, p.PIN_16, p.PIN_17, p.PIN_18, p.PIN_19, p.PIN_20, p.PIN_21,
+	);
 
-	static DISPLAY_BUFFER: StaticCell<LEDs> = StaticCell::new();
+	static DISPLAY_BUFFERS: [StaticCell<LEDs>; 2] = [StaticCell::new(), StaticCell::new()];
 
-	let leds = DISPLAY_BUFFER.init_with(|| cast([0u8; MAX_BUFFER_SIZE]));
-	unwrap!(RETURN_CHANNEL.try_send(leds));
+	for buffer in &DISPLAY_BUFFERS {
+		let leds = buffer.init_with(|| cast([0u8; MAX_BUFFER_SIZE]));
+		unwrap!(RETURN_CHANNEL.try_send(leds));
+	}
 
 	let pio = p.PIO0;
+	let watchdog = p.WATCHDOG;
+
+	let adc = Adc::new(p.ADC, Irqs, AdcConfig::default());
+	let temperature_sensor = AdcChannel::new_temp_sensor(p.ADC_TEMP_SENSOR);
+	let vsys = AdcChannel::new_pin(p.PIN_29, Pull::None);
 
 	// FIXME: taking a mut reference of a static is UB
 	spawn_core1(p.CORE1, unsafe { &mut *addr_of_mut!(CORE1_STACK) }, move || {
 		let executor1 = EXECUTOR1.init(Executor::new());
-		executor1.run(|spawner| unwrap!(spawner.spawn(parallel_led_task(pio, outputs))));
+		executor1.run(|spawner| {
+			unwrap!(spawner.spawn(parallel_led_task(pio, outputs)));
+			unwrap!(spawner.spawn(heartbeat_task()));
+		});
 	});
 
 	// Create the driver, from the HAL.
@@ -92,5 +125,7 @@ fn main() -> ! {
 	let executor0 = EXECUTOR0.init(Executor::new());
 	executor0.run(|spawner| {
 		unwrap!(spawner.spawn(usb_serial_task(driver, id)));
+		unwrap!(spawner.spawn(watchdog_task(watchdog)));
+		unwrap!(spawner.spawn(telemetry_task(adc, temperature_sensor, vsys)));
 	});
 }
@@ -1,39 +1,161 @@
-use core::str::from_utf8;
+use core::{
+	mem,
+	ptr,
+	str::from_utf8,
+	sync::atomic::{AtomicPtr, AtomicU32, Ordering},
+};
 
 use bytemuck::cast_slice;
 use defmt::info;
 use embassy_rp::{
 	peripherals::USB,
+	rom_data::reset_to_usb_boot,
 	usb::{Driver, Instance},
 };
+use embassy_time::{Duration, Timer};
 use embassy_usb::{class::cdc_acm, driver::EndpointError, Builder};
 use futures::future;
 use serial_ws2812_shared::{
+	crc32,
+	fits_packet,
+	parse_command,
+	parse_data,
+	parse_frame_header,
+	Command,
+	DataStep,
+	ParseStep,
+	ResyncDetector,
+	BOOTLOADER_MAGIC,
 	BYTES_PER_LED,
+	CAPABILITIES_LEN,
+	CHECKSUM_LEN,
+	COLOR_ORDER_CHANNELS,
+	COLOR_ORDER_GRB,
+	CONFIG_LEN,
 	DEVICE_ERROR_MESSAGE,
 	DEVICE_MANUFACTURER,
 	DEVICE_OK_MESSAGE,
 	DEVICE_PARTIAL_MESSAGE,
 	DEVICE_PRODUCT_ID,
 	DEVICE_PRODUCT_NAME,
+	DEVICE_RESEND_MESSAGE,
+	DEVICE_RESYNC_MESSAGE,
 	DEVICE_VENDOR_ID,
+	DIFF_ENTRY_LEN,
+	DIFF_INDEX_LEN,
+	FEATURE_COLOR_ORDER,
+	FEATURE_DEVICE_ID,
+	FEATURE_DIFF,
+	FEATURE_GET_CONFIG,
+	FEATURE_LED_TIMING,
+	FEATURE_LENGTH_PREFIX,
+	FEATURE_PING,
+	FEATURE_REBOOT_TO_BOOTLOADER,
+	FEATURE_RESET_DURATION,
+	FEATURE_RESYNC,
+	FEATURE_REVERSED,
+	FEATURE_RLE_UPDATE,
+	FEATURE_STREAM,
+	FEATURE_STRIP_ENABLE,
+	FEATURE_STRIP_LENGTHS,
+	FEATURE_TELEMETRY,
+	FEATURE_UPDATE_CHECKSUM,
+	FEATURE_WAIT_DISPLAYED,
+	FRAME_HEADER_LEN,
+	FRAMING_IMPLICIT,
+	FRAMING_LENGTH_PREFIXED,
+	LED_TIMING_SK6812,
+	LED_TIMING_WS2811_400KHZ,
+	LED_TIMING_WS2812,
 	MAX_BUFFER_SIZE,
 	MAX_LEDS_PER_STRIP,
+	MAX_RESET_DURATION_US,
 	MAX_STRIPS,
 	MESSAGE_NUM_LEN,
 	MESSAGE_TYPE_LEN,
-	SET_LEDS_MESSAGE,
-	SET_STRIPS_MESSAGE,
-	UPDATE_MESSAGE,
+	MIN_RESET_DURATION_US,
+	PIXEL_FORMAT_RGB,
+	RLE_COUNT_LEN,
+	RLE_RUN_LEN,
+	STREAM_END_MESSAGE,
+	STREAM_FRAME_MESSAGE,
+	STREAM_MARKER_LEN,
+	STRIP_LENGTHS_LEN,
+	TELEMETRY_LEN,
 };
 
 use crate::{
-	globals::{DISPLAY_CHANNEL, RETURN_CHANNEL},
+	globals::{
+		DisplayCommand,
+		LEDs,
+		DISPLAY_CHANNEL,
+		FRAMES_SHOWN,
+		FRAME_SHOWN_SIGNAL,
+		LED_TIMING,
+		RESET_DURATION_US,
+		RETURN_CHANNEL,
+	},
+	telemetry,
+	vendor,
 	ID_BYTES,
 };
 
 const PACKET_LEN: u8 = 64;
 
+/// The current in milliamps the device tells the host it draws from the bus.
+///
+/// Defaults to 100mA, which covers the RP2040 and the level shifters. Boards that draw more from USB
+/// can set `SERIAL_WS2812_MAX_POWER_MA` when building the firmware, USB 2.0 allows at most 500mA.
+const MAX_POWER_MA: u16 = parse_max_power(option_env!("SERIAL_WS2812_MAX_POWER_MA"));
+
+const fn parse_max_power(value: Option<&str>) -> u16 {
+	let Some(value) = value else {
+		return 100;
+	};
+
+	let digits = value.as_bytes();
+	assert!(!digits.is_empty(), "SERIAL_WS2812_MAX_POWER_MA is empty");
+
+	let mut milliamps = 0;
+	let mut i = 0;
+	while i < digits.len() {
+		assert!(
+			digits[i].is_ascii_digit(),
+			"SERIAL_WS2812_MAX_POWER_MA has to be a number"
+		);
+		milliamps = milliamps * 10 + (digits[i] - b'0') as u16;
+		assert!(milliamps <= 500, "SERIAL_WS2812_MAX_POWER_MA can be at most 500");
+		i += 1;
+	}
+
+	milliamps
+}
+
+/// The buffer that was handed to the LED task last, with two buffers in rotation diffs need to know
+/// which one holds the latest frame.
+static LATEST_FRAME: AtomicPtr<LEDs> = AtomicPtr::new(ptr::null_mut());
+/// Counts the frames handed to the LED task, compared against [`FRAMES_SHOWN`].
+static FRAMES_QUEUED: AtomicU32 = AtomicU32::new(0);
+
+const FEATURES: u32 = FEATURE_STREAM
+	| FEATURE_DIFF
+	| FEATURE_REBOOT_TO_BOOTLOADER
+	| FEATURE_TELEMETRY
+	| FEATURE_DEVICE_ID
+	| FEATURE_STRIP_LENGTHS
+	| FEATURE_PING
+	| FEATURE_WAIT_DISPLAYED
+	| FEATURE_REVERSED
+	| FEATURE_STRIP_ENABLE
+	| FEATURE_RESET_DURATION
+	| FEATURE_LED_TIMING
+	| FEATURE_LENGTH_PREFIX
+	| FEATURE_RESYNC
+	| FEATURE_GET_CONFIG
+	| FEATURE_COLOR_ORDER
+	| FEATURE_UPDATE_CHECKSUM
+	| FEATURE_RLE_UPDATE;
+
 #[embassy_executor::task]
 pub async fn usb_serial_task(driver: Driver<'static, USB>, id: [u8; ID_BYTES]) {
 	info!("Hello from USB task on core 0");
@@ -51,7 +173,7 @@ pub async fn usb_serial_task(driver: Driver<'static, USB>, id: [u8; ID_BYTES]) {
 	config.manufacturer = Some(DEVICE_MANUFACTURER);
 	config.product = Some(DEVICE_PRODUCT_NAME);
 	config.serial_number = Some(from_utf8(&serial).unwrap());
-	config.max_power = 100;
+	config.max_power = MAX_POWER_MA;
 	config.max_packet_size_0 = PACKET_LEN;
 
 	// Required for windows compatiblity.
@@ -92,8 +214,11 @@ pub async fn usb_serial_task(driver: Driver<'static, USB>, id: [u8; ID_BYTES]) {
 			loop {
 				class.wait_connection().await;
 				info!("Connected");
-				let _ = read_serial(&mut class).await;
+				let _ = read_serial(&mut class, &id).await;
 				info!("Disconnected");
+
+				#[cfg(not(feature = "keep-last-frame"))]
+				clear().await;
 			}
 		},
 	)
@@ -111,122 +236,586 @@ impl From<EndpointError> for Disconnected {
 	}
 }
 
-enum Command {
-	Update,
-	SetStrips,
-	SetLeds,
+struct Config {
+	strips:       usize,
+	/// LEDs per strip, either all the same from [`Command::SetLeds`] or set individually.
+	lengths:      [usize; MAX_STRIPS],
+	/// Bitmask of the strips that are wired backwards.
+	reversed:     u32,
+	/// Bitmask of the strips that are enabled.
+	enabled:      u32,
+	/// The `COLOR_ORDER_*` value of every strip.
+	color_orders: [u8; MAX_STRIPS],
+	/// Every command is followed by the length of its data, see [`FRAMING_LENGTH_PREFIXED`].
+	framed:       bool,
 }
 
-struct Config {
-	strips: usize,
-	leds:   usize,
+impl Config {
+	/// The LEDs per strip, strips that aren't configured have none.
+	fn lengths(&self) -> [usize; MAX_STRIPS] {
+		let mut lengths = self.lengths;
+		lengths[self.strips..].fill(0);
+		lengths
+	}
+
+	fn is_reversed(&self, strip: usize) -> bool {
+		self.reversed & (1 << strip) != 0
+	}
+
+	/// The response to [`Command::GetConfig`].
+	fn encode(&self) -> [u8; CONFIG_LEN] {
+		let mut response = [0; CONFIG_LEN];
+		let (strips, rest) = response.split_at_mut(MESSAGE_NUM_LEN);
+		let (lengths, reversed) = rest.split_at_mut(STRIP_LENGTHS_LEN);
+
+		strips.copy_from_slice(&(self.strips as u32).to_le_bytes());
+		for (bytes, len) in lengths.chunks_exact_mut(2).zip(self.lengths()) {
+			bytes.copy_from_slice(&(len as u16).to_le_bytes());
+		}
+		reversed.copy_from_slice(&self.reversed.to_le_bytes());
+
+		response
+	}
+
+	/// The total amount of LEDs in a frame.
+	fn frame_leds(&self) -> usize {
+		self.lengths().iter().sum()
+	}
+
+	/// Finds the strip and LED for an index into the strip-major frame.
+	fn locate(&self, mut idx: usize) -> Option<(usize, usize)> {
+		for (strip, len) in self.lengths().into_iter().enumerate() {
+			if idx < len {
+				let led = if self.is_reversed(strip) { len - 1 - idx } else { idx };
+				return Some((strip, led));
+			}
+			idx -= len;
+		}
+
+		None
+	}
+}
+
+/// Checks that a frame with the strips and lengths fits into the LED buffer, the per strip and
+/// per LED limits alone don't guarantee that once there are more bytes per LED.
+fn fits_buffer(strips: usize, lengths: &[usize; MAX_STRIPS]) -> bool {
+	lengths[..strips].iter().sum::<usize>() * BYTES_PER_LED <= MAX_BUFFER_SIZE
 }
 
 async fn read_serial<'d, T: Instance + 'd>(
 	class: &mut cdc_acm::CdcAcmClass<'d, Driver<'d, T>>,
+	id: &[u8; ID_BYTES],
 ) -> Result<(), Disconnected> {
-	let mut buf = [0; MESSAGE_TYPE_LEN + MAX_BUFFER_SIZE + PACKET_LEN as usize];
+	let mut buffer = [0; FRAME_HEADER_LEN + MAX_BUFFER_SIZE + CHECKSUM_LEN + PACKET_LEN as usize];
 	let mut idx = 0;
 	let mut command = None;
+	let mut resync = ResyncDetector::default();
+
+	let mut cfg = Config {
+		strips:       3,
+		lengths:      [512; MAX_STRIPS],
+		reversed:     0,
+		enabled:      u32::MAX,
+		color_orders: [COLOR_ORDER_GRB; MAX_STRIPS],
+		framed:       false,
+	};
 
-	let mut cfg = Config { strips: 3, leds: 512 };
+	// bytes of the next command that arrived in the same transfer as the last one
+	let mut unparsed = false;
 
 	loop {
-		idx += class.read_packet(&mut buf[idx..]).await?;
-		let buf = &buf[..idx];
-		if buf.len() < 8 {
-			continue;
+		if !mem::take(&mut unparsed) {
+			// `read_packet` needs room for a full packet, if the buffer fills up without a complete
+			// command the host is out of sync, drop what we have instead of overflowing
+			if !fits_packet(buffer.len(), idx, PACKET_LEN as usize) {
+				info!("receive buffer full without a complete command :(");
+
+				class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+				command = None;
+				idx = 0;
+			}
+
+			let read = class.read_packet(&mut buffer[idx..]).await?;
+			let resync_requested = resync.push(&buffer[idx..idx + read]);
+			idx += read;
+
+			if resync_requested {
+				info!("received resync");
+				resync_done(class, &mut cfg).await?;
+				command = None;
+				idx = 0;
+				continue;
+			}
 		}
 
-		if command.is_none() {
-			let incoming = &buf[..8];
-			let new_command = if incoming == UPDATE_MESSAGE {
-				info!("received update command :)");
+		let buf = &buffer[..idx];
 
-				class.write_packet(DEVICE_PARTIAL_MESSAGE).await?;
-				Command::Update
-			} else if incoming == SET_STRIPS_MESSAGE {
-				info!("received set strips command :)");
+		let (current, data, mut consumed) = if cfg.framed {
+			let Some((incoming, len)) = parse_frame_header(buf) else {
+				continue;
+			};
 
-				class.write_packet(DEVICE_PARTIAL_MESSAGE).await?;
-				Command::SetStrips
-			} else if incoming == SET_LEDS_MESSAGE {
-				info!("received set leds command :)");
+			let new_command = match incoming {
+				ParseStep::Command(new_command) if len <= MAX_BUFFER_SIZE + CHECKSUM_LEN => new_command,
+				_ => {
+					// most likely the host doesn't know about the framing, for example the null bytes
+					// it writes to get to the start of a command end up here
+					info!("received invalid framed command, leaving framed mode :(");
 
-				class.write_packet(DEVICE_PARTIAL_MESSAGE).await?;
-				Command::SetLeds
-			} else {
-				info!("received invalid command :(");
+					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+					cfg.framed = false;
+					idx = 0;
+					continue;
+				}
+			};
 
-				class.write_packet(DEVICE_ERROR_MESSAGE).await?;
-				idx = 0;
+			let Some(data) = buf.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + len) else {
 				continue;
 			};
 
-			command = Some(new_command);
-		}
+			match parse_data(new_command, data, cfg.frame_leds()) {
+				DataStep::Complete(expected) if expected.len() == len => {
+					info!("received framed {} command :)", new_command.name());
+					(new_command, data, FRAME_HEADER_LEN + len)
+				}
+				_ => {
+					info!("received framed {} command with invalid data :(", new_command.name());
 
-		match command {
-			None => {
-				unreachable!();
+					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+					idx = 0;
+					continue;
+				}
 			}
-			Some(Command::SetLeds) if buf.len() >= MESSAGE_TYPE_LEN + MESSAGE_NUM_LEN => {
-				let num = usize::from_le_bytes([
-					buf[MESSAGE_TYPE_LEN],
-					buf[MESSAGE_TYPE_LEN + 1],
-					buf[MESSAGE_TYPE_LEN + 2],
-					buf[MESSAGE_TYPE_LEN + 3],
-				]);
+		} else {
+			let current = match command {
+				Some(current) => current,
+				None => match parse_command(buf) {
+					ParseStep::NeedMore => continue,
+					ParseStep::Error => {
+						info!("received invalid command :(");
+
+						class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+						idx = 0;
+						continue;
+					}
+					ParseStep::Command(new_command) => {
+						info!("received {} command :)", new_command.name());
+
+						if !new_command.is_query() {
+							class.write_packet(DEVICE_PARTIAL_MESSAGE).await?;
+						}
+						command = Some(new_command);
+						new_command
+					}
+				},
+			};
 
-				if num > MAX_LEDS_PER_STRIP {
+			let data = match parse_data(current, &buf[MESSAGE_TYPE_LEN..], cfg.frame_leds()) {
+				DataStep::NeedMore => continue,
+				DataStep::Error => {
 					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+					command = None;
+					idx = 0;
 					continue;
 				}
+				DataStep::Complete(data) => data,
+			};
+
+			(current, data, MESSAGE_TYPE_LEN + data.len())
+		};
+
+		match current {
+			Command::SetLeds => {
+				let num = read_num(data);
+
+				// at least one is required, otherwise updates would be empty
+				if num == 0 || num > MAX_LEDS_PER_STRIP || !fits_buffer(cfg.strips, &[num; MAX_STRIPS]) {
+					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+				} else {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
+
+					cfg.lengths = [num; MAX_STRIPS];
+				}
+			}
+			Command::SetStripLengths => {
+				let mut lengths = [0; MAX_STRIPS];
+				for (len, bytes) in lengths.iter_mut().zip(data.chunks_exact(2)) {
+					*len = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+				}
+
+				// like the host, every configured strip needs at least one LED
+				if lengths[..cfg.strips].iter().any(|&len| len == 0)
+					|| lengths.iter().any(|&len| len > MAX_LEDS_PER_STRIP)
+					|| !fits_buffer(cfg.strips, &lengths)
+				{
+					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+				} else {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
 
+					cfg.lengths = lengths;
+				}
+			}
+			Command::SetReversed => {
 				class.write_packet(DEVICE_OK_MESSAGE).await?;
 
-				cfg.leds = num;
+				cfg.reversed = read_num(data) as u32;
 			}
-			Some(Command::SetStrips) if buf.len() >= MESSAGE_TYPE_LEN + MESSAGE_NUM_LEN => {
-				let num = usize::from_le_bytes([
-					buf[MESSAGE_TYPE_LEN],
-					buf[MESSAGE_TYPE_LEN + 1],
-					buf[MESSAGE_TYPE_LEN + 2],
-					buf[MESSAGE_TYPE_LEN + 3],
-				]);
+			Command::SetColorOrder => {
+				if data.iter().any(|&order| order as usize >= COLOR_ORDER_CHANNELS.len()) {
+					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+				} else {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
+
+					cfg.color_orders.copy_from_slice(data);
+				}
+			}
+			Command::SetStripEnable => {
+				class.write_packet(DEVICE_OK_MESSAGE).await?;
 
-				if num > MAX_STRIPS {
+				cfg.enabled = read_num(data) as u32;
+			}
+			Command::SetResetDuration => {
+				let micros = read_num(data) as u32;
+
+				if !(MIN_RESET_DURATION_US..=MAX_RESET_DURATION_US).contains(&micros) {
 					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
-					continue;
+				} else {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
+
+					RESET_DURATION_US.store(micros, Ordering::Relaxed);
+				}
+			}
+			Command::SetLedTiming => {
+				let timing = read_num(data) as u32;
+
+				if ![LED_TIMING_WS2812, LED_TIMING_SK6812, LED_TIMING_WS2811_400KHZ].contains(&timing) {
+					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+				} else {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
+
+					LED_TIMING.store(timing, Ordering::Relaxed);
+				}
+			}
+			Command::SetFraming => match read_num(data) as u32 {
+				FRAMING_IMPLICIT => {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
+					cfg.framed = false;
+				}
+				FRAMING_LENGTH_PREFIXED => {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
+					cfg.framed = true;
 				}
+				_ => class.write_packet(DEVICE_ERROR_MESSAGE).await?,
+			},
+			Command::SetStrips => {
+				let num = read_num(data);
 
+				// at least one is required, otherwise updates would be empty
+				if num == 0 || num > MAX_STRIPS || !fits_buffer(num, &cfg.lengths) {
+					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+				} else {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
+
+					cfg.strips = num;
+					cfg.reversed = 0;
+					cfg.color_orders = [COLOR_ORDER_GRB; MAX_STRIPS];
+				}
+			}
+			Command::Update => {
 				class.write_packet(DEVICE_OK_MESSAGE).await?;
 
-				cfg.strips = num;
+				info!("update command data received");
+				display(&cfg, data).await;
 			}
-			Some(Command::Update) if buf.len() >= MESSAGE_TYPE_LEN + BYTES_PER_LED * cfg.leds * cfg.strips => {
+			Command::UpdateChecked => {
+				let (leds, checksum) = data.split_at(data.len() - CHECKSUM_LEN);
+
+				if crc32(leds) != read_num(checksum) as u32 {
+					info!("update checksum mismatch, requesting resend :(");
+					class.write_packet(DEVICE_RESEND_MESSAGE).await?;
+				} else {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
+
+					info!("checked update command data received");
+					display(&cfg, leds).await;
+				}
+			}
+			Command::Stream => {
+				let ack_interval = read_num(data);
+
 				class.write_packet(DEVICE_OK_MESSAGE).await?;
 
-				info!("update command data received, waiting for data pointer");
-				let leds = RETURN_CHANNEL.receive().await;
-				info!("data pointer received");
+				info!("entering stream mode");
+				stream(class, &mut buffer, &mut cfg, &mut resync, ack_interval.max(1)).await?;
+				info!("left stream mode");
+
+				// the stream used the whole buffer
+				consumed = idx;
+			}
+			Command::Diff => {
+				class.write_packet(DEVICE_OK_MESSAGE).await?;
 
-				let data = &buf[MESSAGE_TYPE_LEN..];
-				for (i, strip) in leds.iter_mut().enumerate().take(cfg.strips) {
-					let start_idx = i * cfg.leds * BYTES_PER_LED;
-					strip[..cfg.leds]
-						.copy_from_slice(cast_slice(&data[start_idx..start_idx + cfg.leds * BYTES_PER_LED]));
+				info!("diff command data received");
+				display_diff(&cfg, &data[MESSAGE_NUM_LEN..]).await;
+			}
+			Command::RleUpdate => {
+				let runs = &data[MESSAGE_NUM_LEN..];
+				let leds: usize = runs.chunks_exact(RLE_RUN_LEN).map(|run| run[0] as usize).sum();
+
+				if leds != cfg.frame_leds() {
+					info!("rle update doesn't cover the frame :(");
+					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+				} else {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
+
+					info!("rle update command data received");
+					display_rle(&cfg, runs).await;
 				}
+			}
+			Command::RebootToBootloader => {
+				if data != BOOTLOADER_MAGIC {
+					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+				} else {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
 
-				DISPLAY_CHANNEL.send((cfg.leds, leds)).await;
-				info!("sent data pointer to leds");
+					info!("rebooting to bootloader");
+					// give the host a chance to receive the response
+					Timer::after(Duration::from_millis(10)).await;
+					unsafe { reset_to_usb_boot(0, 0) };
+				}
 			}
-			_ => {
-				continue;
+			Command::GetCapabilities => {
+				class.write_packet(DEVICE_OK_MESSAGE).await?;
+				class.write_packet(&capabilities()).await?;
+			}
+			Command::GetTelemetry => {
+				let mut response = [0; TELEMETRY_LEN];
+				response[..MESSAGE_NUM_LEN].copy_from_slice(&telemetry::temperature().to_le_bytes());
+				response[MESSAGE_NUM_LEN..].copy_from_slice(&telemetry::vsys().to_le_bytes());
+
+				class.write_packet(DEVICE_OK_MESSAGE).await?;
+				class.write_packet(&response).await?;
+			}
+			Command::GetId => {
+				class.write_packet(DEVICE_OK_MESSAGE).await?;
+				class.write_packet(id).await?;
+			}
+			Command::GetConfig => {
+				class.write_packet(DEVICE_OK_MESSAGE).await?;
+				class.write_packet(&cfg.encode()).await?;
+			}
+			Command::Ping => {
+				class.write_packet(DEVICE_OK_MESSAGE).await?;
+			}
+			Command::WaitDisplayed => {
+				wait_displayed().await;
+				class.write_packet(DEVICE_OK_MESSAGE).await?;
+			}
+			Command::Vendor(id) => {
+				if vendor::handle(&id, &data[MESSAGE_NUM_LEN..]).await {
+					class.write_packet(DEVICE_OK_MESSAGE).await?;
+				} else {
+					class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+				}
 			}
 		}
 
 		command = None;
-		idx = 0;
+		// a host can send the next command without waiting for the response
+		buffer.copy_within(consumed..idx, 0);
+		idx -= consumed;
+		unparsed = idx > 0;
+	}
+}
+
+/// Acknowledges a resync, the device is waiting for a command in the default framing afterwards.
+async fn resync_done<'d, T: Instance + 'd>(
+	class: &mut cdc_acm::CdcAcmClass<'d, Driver<'d, T>>,
+	cfg: &mut Config,
+) -> Result<(), Disconnected> {
+	class.write_packet(DEVICE_RESYNC_MESSAGE).await?;
+	cfg.framed = false;
+
+	Ok(())
+}
+
+fn read_num(data: &[u8]) -> usize {
+	u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize
+}
+
+fn capabilities() -> [u8; CAPABILITIES_LEN] {
+	let values = [MAX_STRIPS as u32, MAX_LEDS_PER_STRIP as u32, PIXEL_FORMAT_RGB, FEATURES];
+
+	let mut response = [0; CAPABILITIES_LEN];
+	for (chunk, value) in response.chunks_exact_mut(MESSAGE_NUM_LEN).zip(values) {
+		chunk.copy_from_slice(&value.to_le_bytes());
+	}
+
+	response
+}
+
+/// Hands the buffer to the LED task and remembers it as the latest frame.
+async fn show(lengths: [usize; MAX_STRIPS], enabled: u32, color_orders: [u8; MAX_STRIPS], buffer: &'static mut LEDs) {
+	LATEST_FRAME.store(buffer as *mut LEDs, Ordering::Relaxed);
+	FRAMES_QUEUED.store(FRAMES_QUEUED.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+
+	DISPLAY_CHANNEL
+		.send(DisplayCommand {
+			lengths,
+			enabled,
+			color_orders,
+			buffer,
+		})
+		.await;
+	info!("sent data pointer to leds");
+}
+
+/// Waits until every frame handed to the LED task so far is on the LEDs.
+async fn wait_displayed() {
+	let queued = FRAMES_QUEUED.load(Ordering::Relaxed);
+
+	// the counters wrap, so compare the distance instead of the values
+	while (queued.wrapping_sub(FRAMES_SHOWN.load(Ordering::Relaxed)) as i32) > 0 {
+		FRAME_SHOWN_SIGNAL.wait().await;
+	}
+}
+
+/// Copies the strip-major `data` into the led buffer and hands it to the LED task.
+async fn display(cfg: &Config, data: &[u8]) {
+	info!("waiting for data pointer");
+	let leds = RETURN_CHANNEL.receive().await;
+	info!("data pointer received");
+
+	let mut start_idx = 0;
+	for (i, (strip, len)) in leds.iter_mut().zip(cfg.lengths()).enumerate() {
+		let end_idx = start_idx + len * BYTES_PER_LED;
+		let src: &[[u8; BYTES_PER_LED]] = cast_slice(&data[start_idx..end_idx]);
+		if cfg.is_reversed(i) {
+			for (dst, src) in strip[..len].iter_mut().zip(src.iter().rev()) {
+				*dst = *src;
+			}
+		} else {
+			strip[..len].copy_from_slice(src);
+		}
+		start_idx = end_idx;
+	}
+
+	show(cfg.lengths(), cfg.enabled, cfg.color_orders, leds).await;
+}
+
+/// Turns off all LEDs.
+#[cfg(not(feature = "keep-last-frame"))]
+async fn clear() {
+	let leds = RETURN_CHANNEL.receive().await;
+
+	for strip in leds.iter_mut() {
+		strip.fill([0; BYTES_PER_LED]);
+	}
+
+	show(
+		[MAX_LEDS_PER_STRIP; MAX_STRIPS],
+		u32::MAX,
+		[COLOR_ORDER_GRB; MAX_STRIPS],
+		leds,
+	)
+	.await;
+	info!("cleared leds");
+}
+
+/// Applies the changes to the last frame and hands it to the LED task.
+async fn display_diff(cfg: &Config, changes: &[u8]) {
+	info!("waiting for data pointer");
+	let leds = RETURN_CHANNEL.receive().await;
+	info!("data pointer received");
+
+	if !ptr::eq(leds, LATEST_FRAME.load(Ordering::Relaxed)) {
+		// this buffer holds an older frame, the changes have to be applied on top of the latest one
+		info!("waiting for latest frame");
+		let latest = RETURN_CHANNEL.receive().await;
+		leds.copy_from_slice(&latest[..]);
+		RETURN_CHANNEL.send(latest).await;
+	}
+
+	for change in changes.chunks_exact(DIFF_ENTRY_LEN) {
+		let idx = u16::from_le_bytes([change[0], change[1]]) as usize;
+		let Some((strip, led)) = cfg.locate(idx) else {
+			continue;
+		};
+
+		leds[strip][led].copy_from_slice(&change[DIFF_INDEX_LEN..]);
+	}
+
+	show(cfg.lengths(), cfg.enabled, cfg.color_orders, leds).await;
+}
+
+/// Decodes the runs into the led buffer and hands it to the LED task, the runs have to cover the
+/// whole frame.
+async fn display_rle(cfg: &Config, runs: &[u8]) {
+	info!("waiting for data pointer");
+	let leds = RETURN_CHANNEL.receive().await;
+	info!("data pointer received");
+
+	let mut idx = 0;
+	for run in runs.chunks_exact(RLE_RUN_LEN) {
+		for _ in 0..run[0] {
+			if let Some((strip, led)) = cfg.locate(idx) {
+				leds[strip][led].copy_from_slice(&run[RLE_COUNT_LEN..]);
+			}
+			idx += 1;
+		}
+	}
+
+	show(cfg.lengths(), cfg.enabled, cfg.color_orders, leds).await;
+}
+
+/// Receives back-to-back frames, each prefixed with [`STREAM_FRAME_MESSAGE`], until the host sends
+/// [`STREAM_END_MESSAGE`] or anything unexpected.
+///
+/// Only every `ack_interval` frames are acknowledged to save the round trips.
+async fn stream<'d, T: Instance + 'd>(
+	class: &mut cdc_acm::CdcAcmClass<'d, Driver<'d, T>>,
+	buffer: &mut [u8],
+	cfg: &mut Config,
+	resync: &mut ResyncDetector,
+	ack_interval: usize,
+) -> Result<(), Disconnected> {
+	let frame_len = STREAM_MARKER_LEN + BYTES_PER_LED * cfg.frame_leds();
+	let mut idx = 0;
+	let mut frames = 0;
+
+	loop {
+		let is_frame = idx >= STREAM_MARKER_LEN && &buffer[..STREAM_MARKER_LEN] == STREAM_FRAME_MESSAGE;
+		if idx < STREAM_MARKER_LEN || (is_frame && idx < frame_len) {
+			let read = class.read_packet(&mut buffer[idx..]).await?;
+			if resync.push(&buffer[idx..idx + read]) {
+				info!("received resync in stream mode");
+				return resync_done(class, cfg).await;
+			}
+
+			idx += read;
+			continue;
+		}
+
+		if !is_frame {
+			if &buffer[..STREAM_MARKER_LEN] == STREAM_END_MESSAGE {
+				class.write_packet(DEVICE_OK_MESSAGE).await?;
+			} else {
+				info!("received invalid stream marker :(");
+				class.write_packet(DEVICE_ERROR_MESSAGE).await?;
+			}
+
+			return Ok(());
+		}
+
+		display(cfg, &buffer[STREAM_MARKER_LEN..frame_len]).await;
+
+		frames += 1;
+		if frames % ack_interval == 0 {
+			class.write_packet(DEVICE_OK_MESSAGE).await?;
+		}
+
+		// the next frame might already be (partially) in the buffer
+		buffer.copy_within(frame_len..idx, 0);
+		idx -= frame_len;
 	}
 }
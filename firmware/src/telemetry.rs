@@ -0,0 +1,64 @@
+use core::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+
+use defmt::{info, warn};
+use embassy_rp::adc::{Adc, Async, Channel};
+use embassy_time::{Duration, Timer};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+const ADC_REFERENCE_MICROVOLTS: i64 = 3_300_000;
+const ADC_MAX: i64 = 4096;
+/// VSYS is connected to the ADC via a 1/3 voltage divider on the pico.
+const VSYS_DIVIDER: i64 = 3;
+
+static TEMPERATURE: AtomicI32 = AtomicI32::new(0);
+static VSYS: AtomicU32 = AtomicU32::new(0);
+
+/// Latest temperature in milli degrees celsius.
+pub fn temperature() -> i32 {
+	TEMPERATURE.load(Ordering::Relaxed)
+}
+
+/// Latest VSYS voltage in millivolts.
+pub fn vsys() -> u32 {
+	VSYS.load(Ordering::Relaxed)
+}
+
+/// Samples the ADC in the background so answering a telemetry request never has to wait for it.
+///
+/// This runs on core 0 and doesn't interfere with the LED output on core 1.
+#[embassy_executor::task]
+pub async fn telemetry_task(
+	mut adc: Adc<'static, Async>,
+	mut temperature_sensor: Channel<'static>,
+	mut vsys: Channel<'static>,
+) {
+	info!("Hello from telemetry task on core 0");
+
+	loop {
+		match adc.read(&mut temperature_sensor).await {
+			Ok(raw) => TEMPERATURE.store(convert_temperature(raw), Ordering::Relaxed),
+			Err(_) => warn!("failed to read temperature sensor"),
+		}
+
+		match adc.read(&mut vsys).await {
+			Ok(raw) => VSYS.store(convert_vsys(raw), Ordering::Relaxed),
+			Err(_) => warn!("failed to read vsys"),
+		}
+
+		Timer::after(SAMPLE_INTERVAL).await;
+	}
+}
+
+fn to_microvolts(raw: u16) -> i64 {
+	raw as i64 * ADC_REFERENCE_MICROVOLTS / ADC_MAX
+}
+
+/// From the RP2040 datasheet: T = 27 - (V - 0.706) / 0.001721
+fn convert_temperature(raw: u16) -> i32 {
+	(27_000 - (to_microvolts(raw) - 706_000) * 1000 / 1721) as i32
+}
+
+fn convert_vsys(raw: u16) -> u32 {
+	(to_microvolts(raw) * VSYS_DIVIDER / 1000) as u32
+}
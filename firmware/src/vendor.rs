@@ -0,0 +1,16 @@
+//! Commands of forks of the firmware, see [`serial_ws2812_shared::VENDOR_COMMAND_PREFIX`].
+//!
+//! Forks add their commands to [`handle`], the rest of the firmware doesn't have to change and
+//! the ids can't collide with commands added upstream.
+
+use defmt::info;
+use serial_ws2812_shared::VENDOR_ID_LEN;
+
+/// Handles the vendor command `id` with its `data`, without the length in front of it.
+///
+/// Returns whether the command was handled, the host receives an error otherwise.
+pub async fn handle(id: &[u8; VENDOR_ID_LEN], data: &[u8]) -> bool {
+	info!("received unknown vendor command {:a} with {} bytes", id, data.len());
+
+	false
+}
@@ -0,0 +1,41 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::info;
+use embassy_rp::{peripherals::WATCHDOG, watchdog::Watchdog};
+use embassy_time::{Duration, Timer};
+
+/// Has to be long enough to never trip during a full frame write, which takes about 15ms.
+const TIMEOUT: Duration = Duration::from_secs(1);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+const FEED_INTERVAL: Duration = Duration::from_millis(250);
+
+static CORE1_ALIVE: AtomicBool = AtomicBool::new(false);
+
+/// Runs on core 1 and signals that its executor is still making progress.
+#[embassy_executor::task]
+pub async fn heartbeat_task() {
+	loop {
+		CORE1_ALIVE.store(true, Ordering::Relaxed);
+		Timer::after(HEARTBEAT_INTERVAL).await;
+	}
+}
+
+/// Runs on core 0 and feeds the watchdog as long as both executors are alive, the chip resets if
+/// either of them hangs.
+#[embassy_executor::task]
+pub async fn watchdog_task(watchdog: WATCHDOG) {
+	info!("Hello from watchdog task on core 0");
+
+	let mut watchdog = Watchdog::new(watchdog);
+	watchdog.pause_on_debug(true);
+	watchdog.start(TIMEOUT);
+
+	loop {
+		Timer::after(FEED_INTERVAL).await;
+
+		if CORE1_ALIVE.load(Ordering::Relaxed) {
+			CORE1_ALIVE.store(false, Ordering::Relaxed);
+			watchdog.feed();
+		}
+	}
+}
@@ -1,28 +1,65 @@
-use bytemuck::{cast, cast_mut, cast_ref};
+use core::sync::atomic::Ordering;
+
+use bytemuck::cast;
 use defmt::*;
+#[cfg(not(any(feature = "pins-8-15", feature = "pins-14-21")))]
+use embassy_rp::peripherals::{PIN_0, PIN_1, PIN_2, PIN_3, PIN_4, PIN_5, PIN_6, PIN_7};
+#[cfg(feature = "pins-8-15")]
+use embassy_rp::peripherals::{PIN_10, PIN_11, PIN_12, PIN_13, PIN_14, PIN_15, PIN_8, PIN_9};
+#[cfg(feature = "pins-14-21")]
+use embassy_rp::peripherals::{PIN_14, PIN_15, PIN_16, PIN_17, PIN_18, PIN_19, PIN_20, PIN_21};
 use embassy_rp::{
-	peripherals::{PIN_0, PIN_1, PIN_2, PIN_3, PIN_4, PIN_5, PIN_6, PIN_7, PIO0},
-	pio::{Config, Direction, FifoJoin, Instance, Pio, ShiftConfig, ShiftDirection, StateMachine},
+	clocks::clk_sys_freq,
+	peripherals::PIO0,
+	pio::{Config, Direction, FifoJoin, Instance, LoadedProgram, Pin, Pio, ShiftConfig, ShiftDirection, StateMachine},
 };
 use embassy_time::{Duration, Instant, Timer};
+use fixed::types::U24F8;
 use fixed_macro::fixed;
 use pio_proc::pio_asm;
-use serial_ws2812_shared::{BYTES_PER_LED, MAX_BUFFER_SIZE, MAX_STRIPS};
+use serial_ws2812_shared::{
+	compress_byte,
+	BYTES_PER_LED,
+	COLOR_ORDER_CHANNELS,
+	LED_TIMING_SK6812,
+	LED_TIMING_WS2811_400KHZ,
+	LED_TIMING_WS2812,
+	MAX_BUFFER_SIZE,
+	MAX_STRIPS,
+};
 
 use crate::{
-	globals::{LEDs, DISPLAY_CHANNEL, RETURN_CHANNEL},
+	globals::{
+		DisplayCommand,
+		LEDs,
+		DISPLAY_CHANNEL,
+		FRAMES_SHOWN,
+		FRAME_SHOWN_SIGNAL,
+		LED_TIMING,
+		RESET_DURATION_US,
+		RETURN_CHANNEL,
+	},
 	Irqs,
 };
 
-type OutputPins = (PIN_0, PIN_1, PIN_2, PIN_3, PIN_4, PIN_5, PIN_6, PIN_7);
+#[cfg(all(feature = "pins-8-15", feature = "pins-14-21"))]
+compile_error!("only one of the pins-* features can be enabled");
 
-const RESET_DURATION: Duration = Duration::from_micros(280);
+/// The GPIOs the strips are connected to, in order of the strips. The PIO sets all of them at once,
+/// so they have to be consecutive.
+#[cfg(not(any(feature = "pins-8-15", feature = "pins-14-21")))]
+pub type OutputPins = (PIN_0, PIN_1, PIN_2, PIN_3, PIN_4, PIN_5, PIN_6, PIN_7);
+#[cfg(feature = "pins-8-15")]
+pub type OutputPins = (PIN_8, PIN_9, PIN_10, PIN_11, PIN_12, PIN_13, PIN_14, PIN_15);
+#[cfg(feature = "pins-14-21")]
+pub type OutputPins = (PIN_14, PIN_15, PIN_16, PIN_17, PIN_18, PIN_19, PIN_20, PIN_21);
 
 #[embassy_executor::task]
 pub async fn parallel_led_task(pio: PIO0, outputs: OutputPins) {
 	info!("Hello from LED task on core 1");
 
-	let mut sm = setup_ws2812_pio(pio, outputs);
+	let (mut sm, timing_configs) = setup_ws2812_pio(pio, outputs);
+	let mut timing = LED_TIMING_WS2812;
 
 	// allocate as u32 for correct byte alignment
 	let mut out_buf: [u8; MAX_BUFFER_SIZE] = cast([0u32; MAX_BUFFER_SIZE / 4]);
@@ -30,16 +67,33 @@ pub async fn parallel_led_task(pio: PIO0, outputs: OutputPins) {
 	let mut last_write = Instant::now();
 	loop {
 		info!("ws2812: waiting for data pointer");
-		let (num_leds, leds) = DISPLAY_CHANNEL.receive().await;
+		let DisplayCommand {
+			lengths,
+			enabled,
+			color_orders,
+			buffer: leds,
+		} = DISPLAY_CHANNEL.receive().await;
+
+		let reset_duration = Duration::from_micros(RESET_DURATION_US.load(Ordering::Relaxed) as u64);
 
 		// make sure we wait long enough for the ws2812 chips to reset
 		let diff = Instant::now() - last_write;
-		if diff < RESET_DURATION {
-			Timer::after(RESET_DURATION - diff).await;
+		if diff < reset_duration {
+			Timer::after(reset_duration - diff).await;
+		}
+
+		// only switched between frames, while the outputs are low
+		let next_timing = LED_TIMING.load(Ordering::Relaxed);
+		if next_timing != timing {
+			info!("ws2812: switching to timing {}", next_timing);
+			sm.set_enable(false);
+			sm.set_config(&timing_configs[next_timing as usize]);
+			sm.set_enable(true);
+			timing = next_timing;
 		}
 
 		info!("ws2812: got data pointer, writing to GPIO");
-		write_data_direct(&mut sm, leds, num_leds, &mut out_buf).await;
+		write_data_direct(&mut sm, leds, &lengths, enabled, &color_orders, &mut out_buf).await;
 
 		info!("ws2812: done writing to GPIO, returning data pointer");
 		RETURN_CHANNEL.send(leds).await;
@@ -48,39 +102,56 @@ pub async fn parallel_led_task(pio: PIO0, outputs: OutputPins) {
 			Timer::after(Duration::from_micros(5)).await;
 		}
 		last_write = Instant::now();
+
+		// the frame is only latched once the reset period passed, the next frame has to wait for it
+		// anyways
+		Timer::after(reset_duration).await;
+		FRAMES_SHOWN.store(FRAMES_SHOWN.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+		FRAME_SHOWN_SIGNAL.signal(());
 	}
 }
 
 async fn write_data_direct<PIO: Instance>(
 	sm: &mut StateMachine<'_, PIO, 0>,
 	leds: &LEDs,
-	to_write: usize,
+	lengths: &[usize; MAX_STRIPS],
+	enabled: u32,
+	color_orders: &[u8; MAX_STRIPS],
 	out: &mut [u8; MAX_BUFFER_SIZE],
 ) {
-	let mut current;
+	let mut current: [u8; MAX_STRIPS];
 	let mut written_bytes = 0;
 
+	// for every byte sent per LED, which color it is on every strip
+	let channels: [[usize; MAX_STRIPS]; BYTES_PER_LED] = core::array::from_fn(|byte| {
+		core::array::from_fn(|strip| COLOR_ORDER_CHANNELS[color_orders[strip] as usize][byte])
+	});
+
+	let to_write = lengths.iter().copied().max().unwrap_or(0);
 	let leds_to_write = to_write.min(leds[0].len());
 	let tx = sm.tx();
 
 	for i in 0..leds_to_write {
 		let byte_idx = BYTES_PER_LED * MAX_STRIPS * i;
+		// strips that are shorter (or not configured) are masked out, so stale data in the buffer
+		// never reaches them, disabled strips get zeros
+		let strip_mask = strip_mask(lengths, enabled, i);
 
-		// G R B, not R G B
-		for (j, color) in [1, 0, 2].into_iter().enumerate() {
+		for (j, color) in channels.iter().enumerate() {
 			current = [
-				leds[0][i][color],
-				leds[1][i][color],
-				leds[2][i][color],
-				leds[3][i][color],
-				leds[4][i][color],
-				leds[5][i][color],
-				leds[6][i][color],
-				leds[7][i][color],
+				leds[0][i][color[0]],
+				leds[1][i][color[1]],
+				leds[2][i][color[2]],
+				leds[3][i][color[3]],
+				leds[4][i][color[4]],
+				leds[5][i][color[5]],
+				leds[6][i][color[6]],
+				leds[7][i][color[7]],
 			];
+			current = cast(cast::<_, u64>(current) & strip_mask);
 			let start_index = byte_idx + j * 8;
 
-			compress_byte(&mut current, &mut out[start_index..start_index + 8]);
+			compress_byte(&current, &mut out[start_index..start_index + 8]);
 		}
 
 		while byte_idx - written_bytes >= 4 && !tx.full() {
@@ -112,7 +183,9 @@ async fn write_data_direct<PIO: Instance>(
 	}
 }
 
-fn setup_ws2812_pio<'a>(pio: PIO0, outputs: OutputPins) -> StateMachine<'a, PIO0, 0> {
+/// Sets up the state machine with the default timing, also returns the configs for all timings
+/// indexed by the `LED_TIMING_*` values.
+fn setup_ws2812_pio<'a>(pio: PIO0, outputs: OutputPins) -> (StateMachine<'a, PIO0, 0>, [Config<'a, PIO0>; 3]) {
 	let Pio {
 		mut common,
 		sm0: mut sm,
@@ -133,7 +206,8 @@ fn setup_ws2812_pio<'a>(pio: PIO0, outputs: OutputPins) -> StateMachine<'a, PIO0
 	sm.set_pin_dirs(Direction::Out, &pins);
 
 	// adapted from https://mcuoneclipse.com/2023/04/02/rp2040-with-pio-and-dma-to-address-ws2812b-leds/
-	let prg = pio_asm!(
+	// 0 bits are high for T1, 1 bits for T1 + T2
+	let long_pulses = pio_asm!(
 		"
 			.wrap_target
 				mov x, null           ; [1] clear X scratch register
@@ -144,15 +218,47 @@ fn setup_ws2812_pio<'a>(pio: PIO0, outputs: OutputPins) -> StateMachine<'a, PIO0
 			.wrap
 		"
 	);
+	let short_pulses = pio_asm!(
+		"
+			.wrap_target
+				mov x, null           ; [1] clear X scratch register
+				out x, 8              ; [1] copy 8bits from OSR to X
+				mov pins, !null [1]   ; [2] T1: set all pins HIGH (!NULL)
+				mov pins, x     [2]   ; [3] T2: pulse width: keep pins high (for 1 bits) or pull low (for 0 bits)
+				mov pins, null  [2]   ; [3] T3: pull all pins low
+			.wrap
+		"
+	);
+
+	let long_pulses = common.load_program(&long_pulses.program);
+	let short_pulses = common.load_program(&short_pulses.program);
+
+	let timing_configs = [
+		timing_config(&long_pulses, fixed!(800: U24F8), &pins),
+		timing_config(&short_pulses, fixed!(800: U24F8), &pins),
+		timing_config(&short_pulses, fixed!(400: U24F8), &pins),
+	];
+	const _: () = assert!(LED_TIMING_WS2812 == 0 && LED_TIMING_SK6812 == 1 && LED_TIMING_WS2811_400KHZ == 2);
 
+	sm.set_config(&timing_configs[LED_TIMING_WS2812 as usize]);
+	sm.set_enable(true);
+
+	(sm, timing_configs)
+}
+
+/// Runs `program` so it outputs `ws2812_freq` kHz, both programs take the same cycles per bit.
+fn timing_config<'a>(
+	program: &LoadedProgram<'a, PIO0>,
+	ws2812_freq: U24F8,
+	pins: &[&Pin<'a, PIO0>],
+) -> Config<'a, PIO0> {
 	const CYCLES_PER_BIT: u32 = 1 + 1 + 3 + 4 + 1;
 
 	let mut cfg = Config::default();
-	cfg.use_program(&common.load_program(&prg.program), &[]);
+	cfg.use_program(program, &[]);
 
-	// sys clk freq: overclocked in main.rs
-	let clock_freq = fixed!(266_000: U24F8);
-	let ws2812_freq = fixed!(800: U24F8);
+	// sys clk freq in kHz: 266MHz, or 125MHz with the no-overclock feature
+	let clock_freq = U24F8::from_num(clk_sys_freq() / 1000);
 	let bit_freq = ws2812_freq * CYCLES_PER_BIT;
 
 	cfg.clock_divider = clock_freq / bit_freq;
@@ -165,43 +271,18 @@ fn setup_ws2812_pio<'a>(pio: PIO0, outputs: OutputPins) -> StateMachine<'a, PIO0
 
 	cfg.fifo_join = FifoJoin::TxOnly;
 
-	cfg.set_out_pins(&pins);
+	cfg.set_out_pins(pins);
 	cfg.set_set_pins(&pins[0..4]);
 
-	sm.set_config(&cfg);
-	sm.set_enable(true);
-
-	sm
-}
-
-/// splits bytes by bits
-/// nth bit of each byte is combined into the nth byte
-#[inline]
-pub fn compress_byte(i: &mut [u8; 8], out: &mut [u8]) {
-	for bit in out.iter_mut() {
-		*bit = compress_bit(i);
-
-		shift(i)
-	}
-}
-
-#[inline]
-pub fn compress_bit(i: &[u8; 8]) -> u8 {
-	let [lower, upper] = cast_ref::<[u8; 8], [u32; 2]>(i);
-	let lower = lower & 0x80_80_80_80_u32;
-	let upper = upper & 0x80_80_80_80_u32;
-
-	let merge = upper | (lower >> 4);
-	let merge = merge | ((merge >> 2) << 16);
-	let merge = merge | ((merge >> 1) << 8);
-
-	u32::to_be_bytes(merge)[0]
+	cfg
 }
 
+/// Mask for the bytes of the enabled strips that have an LED at index `led`.
 #[inline]
-fn shift(i: &mut [u8; 8]) {
-	let [lower, upper] = cast_mut::<[u8; 8], [u32; 2]>(i);
-	// let [lower, upper] = unsafe { transmute::<&mut [u8; 8], &mut [u32; 2]>(i) };
-	*lower <<= 1;
-	*upper <<= 1;
+fn strip_mask(lengths: &[usize; MAX_STRIPS], enabled: u32, led: usize) -> u64 {
+	lengths
+		.iter()
+		.enumerate()
+		.filter(|&(strip, &len)| led < len && enabled & (1 << strip) != 0)
+		.fold(0, |mask, (strip, _)| mask | 0xff << (strip * 8))
 }
@@ -0,0 +1,606 @@
+//! Framing of the commands the host sends, without any I/O so it can be used (and tested) outside the
+//! firmware.
+
+use crate::{
+	BOOTLOADER_MAGIC,
+	BYTES_PER_LED,
+	CHECKSUM_LEN,
+	COLOR_ORDER_LEN,
+	DIFF_ENTRY_LEN,
+	DIFF_MESSAGE,
+	FRAME_HEADER_LEN,
+	GET_CAPABILITIES_MESSAGE,
+	GET_CONFIG_MESSAGE,
+	GET_ID_MESSAGE,
+	GET_TELEMETRY_MESSAGE,
+	MAX_BUFFER_SIZE,
+	MAX_VENDOR_DATA_LEN,
+	MESSAGE_NUM_LEN,
+	MESSAGE_TYPE_LEN,
+	PING_MESSAGE,
+	REBOOT_TO_BOOTLOADER_MESSAGE,
+	RESYNC_LEN,
+	RESYNC_MESSAGE,
+	RLE_RUN_LEN,
+	RLE_UPDATE_MESSAGE,
+	SET_COLOR_ORDER_MESSAGE,
+	SET_FRAMING_MESSAGE,
+	SET_LEDS_MESSAGE,
+	SET_LED_TIMING_MESSAGE,
+	SET_RESET_DURATION_MESSAGE,
+	SET_REVERSED_MESSAGE,
+	SET_STRIPS_MESSAGE,
+	SET_STRIP_ENABLE_MESSAGE,
+	SET_STRIP_LENGTHS_MESSAGE,
+	STREAM_MESSAGE,
+	STRIP_LENGTHS_LEN,
+	UPDATE_CHECKED_MESSAGE,
+	UPDATE_MESSAGE,
+	VENDOR_COMMAND_PREFIX,
+	VENDOR_ID_LEN,
+	WAIT_DISPLAYED_MESSAGE,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+	Update,
+	UpdateChecked,
+	SetStrips,
+	SetLeds,
+	SetStripLengths,
+	SetReversed,
+	SetStripEnable,
+	SetResetDuration,
+	SetLedTiming,
+	SetColorOrder,
+	SetFraming,
+	Stream,
+	Diff,
+	RleUpdate,
+	RebootToBootloader,
+	GetCapabilities,
+	GetTelemetry,
+	GetId,
+	GetConfig,
+	Ping,
+	WaitDisplayed,
+	/// A command of a fork of the firmware with its id, see [`VENDOR_COMMAND_PREFIX`].
+	Vendor([u8; VENDOR_ID_LEN]),
+}
+
+impl Command {
+	/// Every built-in command, without [`Command::Vendor`].
+	pub const ALL: [Command; 21] = [
+		Command::Update,
+		Command::UpdateChecked,
+		Command::SetStrips,
+		Command::SetLeds,
+		Command::SetStripLengths,
+		Command::SetReversed,
+		Command::SetStripEnable,
+		Command::SetResetDuration,
+		Command::SetLedTiming,
+		Command::SetColorOrder,
+		Command::SetFraming,
+		Command::Stream,
+		Command::Diff,
+		Command::RleUpdate,
+		Command::RebootToBootloader,
+		Command::GetCapabilities,
+		Command::GetTelemetry,
+		Command::GetId,
+		Command::GetConfig,
+		Command::Ping,
+		Command::WaitDisplayed,
+	];
+
+	/// The bytes that identify the command on the wire.
+	pub fn message(self) -> [u8; MESSAGE_TYPE_LEN] {
+		let message = match self {
+			Command::Update => UPDATE_MESSAGE,
+			Command::UpdateChecked => UPDATE_CHECKED_MESSAGE,
+			Command::SetStrips => SET_STRIPS_MESSAGE,
+			Command::SetLeds => SET_LEDS_MESSAGE,
+			Command::SetStripLengths => SET_STRIP_LENGTHS_MESSAGE,
+			Command::SetReversed => SET_REVERSED_MESSAGE,
+			Command::SetStripEnable => SET_STRIP_ENABLE_MESSAGE,
+			Command::SetResetDuration => SET_RESET_DURATION_MESSAGE,
+			Command::SetLedTiming => SET_LED_TIMING_MESSAGE,
+			Command::SetColorOrder => SET_COLOR_ORDER_MESSAGE,
+			Command::SetFraming => SET_FRAMING_MESSAGE,
+			Command::Stream => STREAM_MESSAGE,
+			Command::Diff => DIFF_MESSAGE,
+			Command::RleUpdate => RLE_UPDATE_MESSAGE,
+			Command::RebootToBootloader => REBOOT_TO_BOOTLOADER_MESSAGE,
+			Command::GetCapabilities => GET_CAPABILITIES_MESSAGE,
+			Command::GetTelemetry => GET_TELEMETRY_MESSAGE,
+			Command::GetId => GET_ID_MESSAGE,
+			Command::GetConfig => GET_CONFIG_MESSAGE,
+			Command::Ping => PING_MESSAGE,
+			Command::WaitDisplayed => WAIT_DISPLAYED_MESSAGE,
+			Command::Vendor(id) => {
+				let mut message = [VENDOR_COMMAND_PREFIX; MESSAGE_TYPE_LEN];
+				message[1..].copy_from_slice(&id);
+				return message;
+			}
+		};
+
+		*message
+	}
+
+	/// Human readable name for logging.
+	pub fn name(self) -> &'static str {
+		match self {
+			Command::Update => "update",
+			Command::UpdateChecked => "update checked",
+			Command::SetStrips => "set strips",
+			Command::SetLeds => "set leds",
+			Command::SetStripLengths => "set strip lengths",
+			Command::SetReversed => "set reversed",
+			Command::SetStripEnable => "set strip enable",
+			Command::SetResetDuration => "set reset duration",
+			Command::SetLedTiming => "set led timing",
+			Command::SetColorOrder => "set color order",
+			Command::SetFraming => "set framing",
+			Command::Stream => "stream",
+			Command::Diff => "diff",
+			Command::RleUpdate => "rle update",
+			Command::RebootToBootloader => "reboot to bootloader",
+			Command::GetCapabilities => "get capabilities",
+			Command::GetTelemetry => "get telemetry",
+			Command::GetId => "get id",
+			Command::GetConfig => "get config",
+			Command::Ping => "ping",
+			Command::WaitDisplayed => "wait displayed",
+			Command::Vendor(_) => "vendor",
+		}
+	}
+
+	/// Queries have no data, the device responds right away instead of acknowledging the command
+	/// first.
+	pub fn is_query(self) -> bool {
+		matches!(
+			self,
+			Command::GetCapabilities
+				| Command::GetTelemetry
+				| Command::GetId
+				| Command::GetConfig
+				| Command::Ping
+				| Command::WaitDisplayed
+		)
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseStep {
+	/// Not enough bytes to identify the command yet.
+	NeedMore,
+	Command(Command),
+	/// The bytes are not a known command.
+	Error,
+}
+
+/// Identifies the command at the start of `buf`.
+pub fn parse_command(buf: &[u8]) -> ParseStep {
+	let Some(incoming) = buf.get(..MESSAGE_TYPE_LEN) else {
+		return ParseStep::NeedMore;
+	};
+
+	if let [VENDOR_COMMAND_PREFIX, id @ ..] = incoming {
+		return ParseStep::Command(Command::Vendor(id.try_into().unwrap()));
+	}
+
+	Command::ALL
+		.into_iter()
+		.find(|command| command.message() == incoming)
+		.map_or(ParseStep::Error, ParseStep::Command)
+}
+
+/// Identifies the command and the announced data length of a length-prefixed message at the start
+/// of `buf`, `None` until the header is complete.
+pub fn parse_frame_header(buf: &[u8]) -> Option<(ParseStep, usize)> {
+	let header = buf.get(..FRAME_HEADER_LEN)?;
+	let len = &header[MESSAGE_TYPE_LEN..];
+
+	Some((
+		parse_command(header),
+		u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize,
+	))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataStep<'a> {
+	/// The data of the command is not complete yet.
+	NeedMore,
+	/// The complete data of the command, bytes after it belong to the next command.
+	Complete(&'a [u8]),
+	/// The data can never be valid, the command has to be rejected.
+	Error,
+}
+
+/// Checks if `data`, the bytes received after the command, hold the complete data for it.
+///
+/// `frame_leds` is the total amount of LEDs in the current config of the device, it determines the
+/// size of updates and the valid range of diffs.
+pub fn parse_data(command: Command, data: &[u8], frame_leds: usize) -> DataStep<'_> {
+	let len = match command {
+		Command::GetCapabilities
+		| Command::GetTelemetry
+		| Command::GetId
+		| Command::GetConfig
+		| Command::Ping
+		| Command::WaitDisplayed => 0,
+		Command::SetStrips
+		| Command::SetLeds
+		| Command::SetReversed
+		| Command::SetStripEnable
+		| Command::SetResetDuration
+		| Command::SetLedTiming
+		| Command::SetFraming
+		| Command::Stream => MESSAGE_NUM_LEN,
+		Command::SetStripLengths => STRIP_LENGTHS_LEN,
+		Command::SetColorOrder => COLOR_ORDER_LEN,
+		Command::RebootToBootloader => BOOTLOADER_MAGIC.len(),
+		Command::Update => BYTES_PER_LED * frame_leds,
+		Command::UpdateChecked => BYTES_PER_LED * frame_leds + CHECKSUM_LEN,
+		Command::Diff => {
+			let Some(count) = data.get(..MESSAGE_NUM_LEN) else {
+				return DataStep::NeedMore;
+			};
+			let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+
+			if count > frame_leds || MESSAGE_NUM_LEN + count * DIFF_ENTRY_LEN > MAX_BUFFER_SIZE {
+				return DataStep::Error;
+			}
+
+			MESSAGE_NUM_LEN + count * DIFF_ENTRY_LEN
+		}
+		Command::RleUpdate => {
+			let Some(count) = data.get(..MESSAGE_NUM_LEN) else {
+				return DataStep::NeedMore;
+			};
+			let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+
+			// every run covers at least one LED
+			if count > frame_leds || MESSAGE_NUM_LEN + count * RLE_RUN_LEN > MAX_BUFFER_SIZE {
+				return DataStep::Error;
+			}
+
+			MESSAGE_NUM_LEN + count * RLE_RUN_LEN
+		}
+		Command::Vendor(_) => {
+			let Some(len) = data.get(..MESSAGE_NUM_LEN) else {
+				return DataStep::NeedMore;
+			};
+			let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+
+			if len > MAX_VENDOR_DATA_LEN {
+				return DataStep::Error;
+			}
+
+			MESSAGE_NUM_LEN + len
+		}
+	};
+
+	match data.get(..len) {
+		Some(data) => DataStep::Complete(data),
+		None => DataStep::NeedMore,
+	}
+}
+
+/// Whether another USB packet of up to `packet_len` bytes fits into a receive buffer of `capacity`
+/// bytes that already holds `received` bytes.
+///
+/// A host that sent that much without completing a command is out of sync, the received bytes have
+/// to be dropped once the next packet doesn't fit anymore.
+pub fn fits_packet(capacity: usize, received: usize, packet_len: usize) -> bool {
+	capacity.saturating_sub(received) >= packet_len
+}
+
+/// Watches the received bytes for [`RESYNC_MESSAGE`], which can arrive at any point.
+#[derive(Clone, Debug, Default)]
+pub struct ResyncDetector {
+	/// The last bytes received, as a ring buffer with the oldest at `pos`.
+	recent: [u8; RESYNC_LEN],
+	pos:    usize,
+}
+
+impl ResyncDetector {
+	/// Feeds received bytes, returns `true` if they end with [`RESYNC_MESSAGE`].
+	pub fn push(&mut self, bytes: &[u8]) -> bool {
+		for &byte in bytes {
+			self.recent[self.pos] = byte;
+			self.pos = (self.pos + 1) % RESYNC_LEN;
+		}
+
+		self.recent[self.pos..]
+			.iter()
+			.chain(&self.recent[..self.pos])
+			.eq(RESYNC_MESSAGE)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{DIFF_ENTRY_LEN, MAX_LEDS_PER_STRIP, MAX_STRIPS, RLE_RUN_LEN};
+
+	/// Random bytes without a dependency, the same seed always gives the same streams.
+	struct XorShift(u64);
+
+	impl XorShift {
+		fn next(&mut self) -> u64 {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			self.0
+		}
+
+		fn bytes(&mut self, len: usize) -> Vec<u8> {
+			(0..len).map(|_| self.next() as u8).collect()
+		}
+	}
+
+	/// Data starting with `count` as little endian `u32`.
+	fn with_count(count: u32) -> Vec<u8> {
+		count.to_le_bytes().to_vec()
+	}
+
+	#[test]
+	fn every_command_round_trips() {
+		for command in Command::ALL {
+			assert_eq!(
+				parse_command(&command.message()),
+				ParseStep::Command(command),
+				"{}",
+				command.name()
+			);
+		}
+
+		let vendor = Command::Vendor(*b"example");
+		assert_eq!(parse_command(&vendor.message()), ParseStep::Command(vendor));
+	}
+
+	#[test]
+	fn short_buffers_need_more() {
+		for command in Command::ALL {
+			let message = command.message();
+			for len in 0..MESSAGE_TYPE_LEN {
+				assert_eq!(parse_command(&message[..len]), ParseStep::NeedMore);
+			}
+		}
+
+		let header = [UPDATE_MESSAGE.as_slice(), &[0; MESSAGE_NUM_LEN]].concat();
+		for len in 0..FRAME_HEADER_LEN {
+			assert_eq!(parse_frame_header(&header[..len]), None);
+		}
+	}
+
+	#[test]
+	fn unknown_bytes_are_an_error() {
+		assert_eq!(parse_command(b"unknown\0"), ParseStep::Error);
+		assert_eq!(parse_command(&[0; MESSAGE_TYPE_LEN]), ParseStep::Error);
+		assert_eq!(
+			parse_frame_header(b"unknown\0\0\0\0\0"),
+			Some((ParseStep::Error, 0))
+		);
+	}
+
+	#[test]
+	fn diff_limits() {
+		assert_eq!(
+			parse_data(Command::Diff, &with_count(11), 10),
+			DataStep::Error
+		);
+		assert_eq!(
+			parse_data(Command::Diff, &with_count(10), 10),
+			DataStep::NeedMore
+		);
+
+		// more LEDs than the buffer holds can never be valid
+		let too_many = (MAX_BUFFER_SIZE / DIFF_ENTRY_LEN) as u32 + 1;
+		assert_eq!(
+			parse_data(Command::Diff, &with_count(too_many), MAX_BUFFER_SIZE),
+			DataStep::Error
+		);
+	}
+
+	#[test]
+	fn rle_update_limits() {
+		assert_eq!(
+			parse_data(Command::RleUpdate, &with_count(11), 10),
+			DataStep::Error
+		);
+		assert_eq!(
+			parse_data(Command::RleUpdate, &with_count(10), 10),
+			DataStep::NeedMore
+		);
+
+		let too_many = (MAX_BUFFER_SIZE / RLE_RUN_LEN) as u32 + 1;
+		assert_eq!(
+			parse_data(Command::RleUpdate, &with_count(too_many), MAX_BUFFER_SIZE),
+			DataStep::Error
+		);
+
+		let data = [with_count(1), vec![10, 1, 2, 3]].concat();
+		assert_eq!(
+			parse_data(Command::RleUpdate, &data, 10),
+			DataStep::Complete(&data)
+		);
+	}
+
+	#[test]
+	fn vendor_limits() {
+		let vendor = Command::Vendor(*b"example");
+		let too_long = MAX_VENDOR_DATA_LEN as u32 + 1;
+		assert_eq!(
+			parse_data(vendor, &with_count(too_long), 0),
+			DataStep::Error
+		);
+
+		let data = [
+			with_count(MAX_VENDOR_DATA_LEN as u32),
+			vec![0; MAX_VENDOR_DATA_LEN],
+		]
+		.concat();
+		assert_eq!(parse_data(vendor, &data, 0), DataStep::Complete(&data));
+		assert_eq!(
+			parse_data(vendor, &data[..data.len() - 1], 0),
+			DataStep::NeedMore
+		);
+	}
+
+	#[test]
+	fn back_to_back_commands_split() {
+		let frame_leds = 2 * 3;
+		let frame: Vec<u8> = (0..BYTES_PER_LED * frame_leds).map(|i| i as u8).collect();
+		// both commands arrive in the same transfer
+		let transfer = [
+			SET_STRIPS_MESSAGE.as_slice(),
+			&2u32.to_le_bytes(),
+			UPDATE_MESSAGE,
+			&frame,
+		]
+		.concat();
+
+		assert_eq!(
+			parse_command(&transfer),
+			ParseStep::Command(Command::SetStrips)
+		);
+		let DataStep::Complete(data) = parse_data(
+			Command::SetStrips,
+			&transfer[MESSAGE_TYPE_LEN..],
+			frame_leds,
+		) else {
+			panic!("set strips is complete");
+		};
+		assert_eq!(data, 2u32.to_le_bytes());
+
+		let rest = &transfer[MESSAGE_TYPE_LEN + data.len()..];
+		assert_eq!(parse_command(rest), ParseStep::Command(Command::Update));
+		assert_eq!(
+			parse_data(Command::Update, &rest[MESSAGE_TYPE_LEN..], frame_leds),
+			DataStep::Complete(&frame)
+		);
+	}
+
+	#[test]
+	fn full_buffer_has_no_room_for_a_packet() {
+		assert!(fits_packet(100, 0, 64));
+		assert!(fits_packet(100, 36, 64));
+		assert!(!fits_packet(100, 37, 64));
+		assert!(!fits_packet(100, 100, 64));
+		assert!(!fits_packet(100, 164, 64));
+	}
+
+	/// Feeds `data` to [`parse_data`] `chunk` bytes at a time like the device receives it, it has to
+	/// need more until the last byte arrived.
+	fn assert_complete_after_last_chunk(
+		command: Command,
+		data: &[u8],
+		frame_leds: usize,
+		chunk: usize,
+	) {
+		let mut received = 0;
+		while received + chunk < data.len() {
+			received += chunk;
+			assert_eq!(
+				parse_data(command, &data[..received], frame_leds),
+				DataStep::NeedMore,
+				"{} bytes of {} in chunks of {chunk}",
+				received,
+				data.len()
+			);
+		}
+
+		assert_eq!(
+			parse_data(command, data, frame_leds),
+			DataStep::Complete(data)
+		);
+	}
+
+	#[test]
+	fn full_update_in_packets() {
+		let frame_leds = MAX_LEDS_PER_STRIP * MAX_STRIPS;
+		let frame: Vec<u8> = (0..BYTES_PER_LED * frame_leds).map(|i| i as u8).collect();
+
+		for chunk in [64, 1, 7, 63, 65, 333, 4096] {
+			assert_complete_after_last_chunk(Command::Update, &frame, frame_leds, chunk);
+		}
+
+		// bytes after the frame belong to the next command
+		let longer = [frame.as_slice(), UPDATE_MESSAGE].concat();
+		assert_eq!(
+			parse_data(Command::Update, &longer, frame_leds),
+			DataStep::Complete(&frame)
+		);
+	}
+
+	#[test]
+	fn split_inside_count() {
+		let diff = [with_count(2), vec![0; 2 * DIFF_ENTRY_LEN]].concat();
+		let rle = [with_count(2), vec![5, 1, 2, 3, 5, 4, 5, 6]].concat();
+
+		for (command, data) in [(Command::Diff, diff), (Command::RleUpdate, rle)] {
+			for len in 0..MESSAGE_NUM_LEN {
+				assert_eq!(parse_data(command, &data[..len], 10), DataStep::NeedMore);
+			}
+			for chunk in [1, 2, 3, 5] {
+				assert_complete_after_last_chunk(command, &data, 10, chunk);
+			}
+		}
+	}
+
+	#[test]
+	fn split_inside_frame_header() {
+		let frame_leds = 4;
+		let data = vec![0xaa; BYTES_PER_LED * frame_leds];
+		let framed = [
+			UPDATE_MESSAGE.as_slice(),
+			&(data.len() as u32).to_le_bytes(),
+			&data,
+		]
+		.concat();
+
+		for len in 0..FRAME_HEADER_LEN {
+			assert_eq!(parse_frame_header(&framed[..len]), None);
+		}
+		assert_eq!(
+			parse_frame_header(&framed[..FRAME_HEADER_LEN]),
+			Some((ParseStep::Command(Command::Update), data.len()))
+		);
+		assert_eq!(
+			parse_data(Command::Update, &framed[FRAME_HEADER_LEN..], frame_leds),
+			DataStep::Complete(&data)
+		);
+	}
+
+	#[test]
+	// `is_multiple_of` is newer than the pinned toolchain, which doesn't know the lint either
+	#[allow(unknown_lints, clippy::manual_is_multiple_of)]
+	fn random_streams_never_panic() {
+		let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+		let vendor = Command::Vendor(*b"example");
+		let mut resync = ResyncDetector::default();
+
+		for _ in 0..10_000 {
+			let len = rng.next() as usize % 64;
+			let mut stream = rng.bytes(len);
+			// start with a real command half of the time, so the data is parsed as well
+			if rng.next() % 2 == 0 {
+				let command = Command::ALL[rng.next() as usize % Command::ALL.len()];
+				stream.splice(0..0, command.message());
+			}
+			let frame_leds = rng.next() as usize % (MAX_BUFFER_SIZE + 1);
+
+			if let ParseStep::Command(command) = parse_command(&stream) {
+				let _ = parse_data(command, &stream[MESSAGE_TYPE_LEN..], frame_leds);
+			}
+			let _ = parse_frame_header(&stream);
+			for command in Command::ALL.into_iter().chain([vendor]) {
+				let _ = parse_data(command, &stream, frame_leds);
+			}
+			resync.push(&stream);
+		}
+	}
+}
@@ -0,0 +1,31 @@
+/// The reflected CRC-32 polynomial, as used by zlib and ethernet.
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+/// The CRC of every byte value, so the checksum takes one lookup per byte instead of one step per
+/// bit.
+const TABLE: [u32; 256] = {
+	let mut table = [0; 256];
+	let mut i = 0;
+	while i < table.len() {
+		let mut crc = i as u32;
+		let mut bit = 0;
+		while bit < 8 {
+			crc = if crc & 1 == 1 {
+				(crc >> 1) ^ POLYNOMIAL
+			} else {
+				crc >> 1
+			};
+			bit += 1;
+		}
+		table[i] = crc;
+		i += 1;
+	}
+	table
+};
+
+/// The CRC-32 of `data`, the checksum of [`crate::UPDATE_CHECKED_MESSAGE`].
+pub fn crc32(data: &[u8]) -> u32 {
+	!data.iter().fold(!0, |crc, &byte| {
+		TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+	})
+}
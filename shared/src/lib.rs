@@ -1,12 +1,222 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+
+mod command;
+mod crc;
+mod transpose;
+
+pub use command::{
+	fits_packet,
+	parse_command,
+	parse_data,
+	parse_frame_header,
+	Command,
+	DataStep,
+	ParseStep,
+	ResyncDetector,
+};
+pub use crc::crc32;
+pub use transpose::compress_byte;
 
 pub const MESSAGE_TYPE_LEN: usize = 8;
 pub const MESSAGE_NUM_LEN: usize = 4;
 
 pub const UPDATE_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"update\0\0";
+
+/// Like [`UPDATE_MESSAGE`], but the LED data is followed by its [`crc32`] as little endian `u32`.
+///
+/// If the checksum doesn't match, the device drops the frame and responds with
+/// [`DEVICE_RESEND_MESSAGE`] instead of [`DEVICE_OK_MESSAGE`]. It waits for the next command
+/// afterwards, so the host can send the frame again right away.
+pub const UPDATE_CHECKED_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"updcrc\0\0";
+pub const CHECKSUM_LEN: usize = MESSAGE_NUM_LEN;
 pub const SET_STRIPS_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"strips\0\0";
 pub const SET_LEDS_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"leds\0\0\0\0";
 
+/// Sets the amount of LEDs for every strip individually, the data is one little endian `u16` per
+/// strip.
+///
+/// This overrides [`SET_LEDS_MESSAGE`] until it is sent again.
+pub const SET_STRIP_LENGTHS_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"lengths\0";
+pub const STRIP_LENGTHS_LEN: usize = 2 * MAX_STRIPS;
+
+/// Sets which strips are wired backwards, the data is a little endian `u32` bitmask with a bit per
+/// strip. The LED data of these strips is flipped on the device.
+///
+/// [`SET_STRIPS_MESSAGE`] resets this to no reversed strips.
+pub const SET_REVERSED_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"reversed";
+
+/// Sets which strips are enabled, the data is a little endian `u32` bitmask with a bit per strip.
+/// Disabled strips are kept dark regardless of their LED data, all strips are enabled at startup.
+pub const SET_STRIP_ENABLE_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"enable\0\0";
+
+/// Sets how long the outputs are kept low after a frame so the LEDs latch it, the data is the
+/// duration in microseconds as little endian `u32`, between [`MIN_RESET_DURATION_US`] and
+/// [`MAX_RESET_DURATION_US`].
+pub const SET_RESET_DURATION_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"latch\0\0\0";
+/// The reset duration the WS2812B datasheet requires, also the default.
+pub const MIN_RESET_DURATION_US: u32 = 280;
+pub const MAX_RESET_DURATION_US: u32 = 10_000;
+
+/// Selects the bit timing of the outputs, the data is one of the `LED_TIMING_*` values as little
+/// endian `u32`.
+pub const SET_LED_TIMING_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"timing\0\0";
+/// 800kHz, 375ns/875ns high for 0/1 bits, the default.
+pub const LED_TIMING_WS2812: u32 = 0;
+/// 800kHz, 250ns/625ns high for 0/1 bits, also WS2811 in high speed mode.
+pub const LED_TIMING_SK6812: u32 = 1;
+/// 400kHz, 500ns/1250ns high for 0/1 bits.
+pub const LED_TIMING_WS2811_400KHZ: u32 = 2;
+
+/// Sets the order every strip expects the color channels in, the data is one of the
+/// `COLOR_ORDER_*` values per strip, a byte each.
+///
+/// [`SET_STRIPS_MESSAGE`] resets this to [`COLOR_ORDER_GRB`] for all strips.
+pub const SET_COLOR_ORDER_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"corder\0\0";
+pub const COLOR_ORDER_LEN: usize = MAX_STRIPS;
+/// Green, red, blue like the WS2812, the default.
+pub const COLOR_ORDER_GRB: u8 = 0;
+pub const COLOR_ORDER_RGB: u8 = 1;
+pub const COLOR_ORDER_BRG: u8 = 2;
+pub const COLOR_ORDER_RBG: u8 = 3;
+pub const COLOR_ORDER_GBR: u8 = 4;
+pub const COLOR_ORDER_BGR: u8 = 5;
+/// For every `COLOR_ORDER_*` value, the index of the red, green or blue byte of an LED that is sent
+/// first, second and third.
+pub const COLOR_ORDER_CHANNELS: [[usize; BYTES_PER_LED]; 6] = [
+	[1, 0, 2],
+	[0, 1, 2],
+	[2, 0, 1],
+	[0, 2, 1],
+	[1, 2, 0],
+	[2, 1, 0],
+];
+
+/// Selects how the device finds the end of a message, the data is one of the `FRAMING_*` values as
+/// little endian `u32`.
+pub const SET_FRAMING_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"framing\0";
+/// The device computes the length of the data from the command and its config, the default.
+pub const FRAMING_IMPLICIT: u32 = 0;
+/// Every command is followed by the length of its data as little endian `u32` and then the data,
+/// the device doesn't acknowledge the command with [`DEVICE_PARTIAL_MESSAGE`].
+///
+/// The device always consumes exactly the announced length, data that doesn't match the command is
+/// rejected with [`DEVICE_ERROR_MESSAGE`] without losing track of where the next command starts. An
+/// unknown command or a length that can never fit switches the device back to
+/// [`FRAMING_IMPLICIT`], as the host is most likely not sending framed messages at all.
+///
+/// Streamed frames and [`STREAM_END_MESSAGE`] are not framed.
+pub const FRAMING_LENGTH_PREFIXED: u32 = 1;
+pub const FRAME_LENGTH_LEN: usize = MESSAGE_NUM_LEN;
+pub const FRAME_HEADER_LEN: usize = MESSAGE_TYPE_LEN + FRAME_LENGTH_LEN;
+
+/// Returns the device to waiting for a command from any state, even in the middle of the data of a
+/// command or in streaming mode. The device responds with [`DEVICE_RESYNC_MESSAGE`] and goes back to
+/// [`FRAMING_IMPLICIT`], the rest of the interrupted command is dropped.
+///
+/// Unlike the other messages this is recognized when it is the last thing the device received, so
+/// the host has to wait for the response before sending anything else.
+pub const RESYNC_MESSAGE: &[u8; RESYNC_LEN] = b"\xffresync\0\xffRESYNC\0";
+pub const RESYNC_LEN: usize = 16;
+
+/// Switches the device into streaming mode, the data is the amount of frames after which the device
+/// acknowledges with [`DEVICE_OK_MESSAGE`].
+pub const STREAM_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"stream\0\0";
+
+/// Changes single LEDs of the last frame, the data is the amount of changes followed by the changes
+/// themselves.
+///
+/// Every change is the strip-major LED index as little endian `u16` followed by the color.
+pub const DIFF_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"diff\0\0\0\0";
+
+pub const DIFF_INDEX_LEN: usize = 2;
+pub const DIFF_ENTRY_LEN: usize = DIFF_INDEX_LEN + BYTES_PER_LED;
+
+/// Like [`UPDATE_MESSAGE`], but the frame is run-length encoded, the data is the amount of runs
+/// followed by the runs themselves.
+///
+/// Every run is the amount of LEDs as `u8` followed by their color, in the strip-major order of
+/// updates. The runs have to cover exactly the LEDs of the frame, otherwise the device rejects it
+/// with [`DEVICE_ERROR_MESSAGE`].
+pub const RLE_UPDATE_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"rle\0\0\0\0\0";
+
+pub const RLE_COUNT_LEN: usize = 1;
+pub const RLE_RUN_LEN: usize = RLE_COUNT_LEN + BYTES_PER_LED;
+
+/// Reboots the device into the UF2 bootloader, the data has to be [`BOOTLOADER_MAGIC`].
+pub const REBOOT_TO_BOOTLOADER_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"bootsel\0";
+/// Guards the reboot so stray bytes can never trigger it.
+pub const BOOTLOADER_MAGIC: &[u8; MESSAGE_NUM_LEN] = b"UF2!";
+
+/// Queries the capabilities of the device.
+///
+/// Queries have no data, the device responds with [`DEVICE_OK_MESSAGE`] followed by the response.
+pub const GET_CAPABILITIES_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"caps\0\0\0\0";
+/// Max strips, max leds per strip, pixel formats and features, each as little endian `u32`.
+pub const CAPABILITIES_LEN: usize = 4 * MESSAGE_NUM_LEN;
+
+pub const PIXEL_FORMAT_RGB: u32 = 1 << 0;
+
+pub const FEATURE_STREAM: u32 = 1 << 0;
+pub const FEATURE_DIFF: u32 = 1 << 1;
+pub const FEATURE_REBOOT_TO_BOOTLOADER: u32 = 1 << 2;
+pub const FEATURE_TELEMETRY: u32 = 1 << 3;
+pub const FEATURE_DEVICE_ID: u32 = 1 << 4;
+pub const FEATURE_STRIP_LENGTHS: u32 = 1 << 5;
+pub const FEATURE_PING: u32 = 1 << 6;
+pub const FEATURE_WAIT_DISPLAYED: u32 = 1 << 7;
+pub const FEATURE_REVERSED: u32 = 1 << 8;
+pub const FEATURE_STRIP_ENABLE: u32 = 1 << 9;
+pub const FEATURE_RESET_DURATION: u32 = 1 << 10;
+pub const FEATURE_LED_TIMING: u32 = 1 << 11;
+pub const FEATURE_LENGTH_PREFIX: u32 = 1 << 12;
+pub const FEATURE_RESYNC: u32 = 1 << 13;
+pub const FEATURE_GET_CONFIG: u32 = 1 << 14;
+pub const FEATURE_COLOR_ORDER: u32 = 1 << 15;
+pub const FEATURE_UPDATE_CHECKSUM: u32 = 1 << 16;
+pub const FEATURE_RLE_UPDATE: u32 = 1 << 17;
+
+/// Queries the chip temperature and supply voltage.
+pub const GET_TELEMETRY_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"telem\0\0\0";
+/// Temperature in milli degrees celsius as little endian `i32` followed by the VSYS voltage in
+/// millivolts as little endian `u32`.
+pub const TELEMETRY_LEN: usize = 2 * MESSAGE_NUM_LEN;
+
+/// Queries the unique id of the device, the flash JEDEC id followed by the flash unique id.
+pub const GET_ID_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"id\0\0\0\0\0\0";
+pub const DEVICE_ID_LEN: usize = 20;
+
+/// Queries the strips the device is currently configured with.
+pub const GET_CONFIG_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"getcfg\0\0";
+/// The amount of strips as little endian `u32`, the LEDs of every strip like the data of
+/// [`SET_STRIP_LENGTHS_MESSAGE`] and the bitmask of reversed strips as little endian `u32`.
+pub const CONFIG_LEN: usize = MESSAGE_NUM_LEN + STRIP_LENGTHS_LEN + MESSAGE_NUM_LEN;
+
+/// Checks that the device is alive, it responds with [`DEVICE_OK_MESSAGE`] without any further
+/// data.
+pub const PING_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"ping\0\0\0\0";
+
+/// Waits until the last frame was written to the LEDs and latched, the device responds with
+/// [`DEVICE_OK_MESSAGE`] without any further data once that happened.
+pub const WAIT_DISPLAYED_MESSAGE: &[u8; MESSAGE_TYPE_LEN] = b"wait\0\0\0\0";
+
+/// Commands starting with this byte are reserved for extensions of forks of the firmware, none of
+/// the built-in commands ever will.
+///
+/// The rest of the command is an id the fork chooses, the data is the length of the vendor data as
+/// little endian `u32` followed by the data itself. The device responds like for any other command,
+/// firmware that doesn't know the id rejects it with [`DEVICE_ERROR_MESSAGE`].
+pub const VENDOR_COMMAND_PREFIX: u8 = b'@';
+pub const VENDOR_ID_LEN: usize = MESSAGE_TYPE_LEN - 1;
+/// The most data a vendor command can have, without its length.
+pub const MAX_VENDOR_DATA_LEN: usize = MAX_BUFFER_SIZE - MESSAGE_NUM_LEN;
+
+pub const STREAM_MARKER_LEN: usize = 1;
+
+/// In streaming mode every frame is prefixed with this marker.
+pub const STREAM_FRAME_MESSAGE: &[u8; STREAM_MARKER_LEN] = b"f";
+/// Leaves streaming mode, the device acknowledges with [`DEVICE_OK_MESSAGE`].
+pub const STREAM_END_MESSAGE: &[u8; STREAM_MARKER_LEN] = b"x";
+
 /// This has to be 8 because the PIO "script" always writes 8 strips in parallel.
 pub const MAX_STRIPS: usize = 8;
 /// This could be increased, but you will get less than 60 updates per second.
@@ -21,6 +231,9 @@ pub const DEVICE_INIT_MESSAGE: &[u8; DEVICE_MESSAGE_TYPE_LEN] = b"i";
 pub const DEVICE_ERROR_MESSAGE: &[u8; DEVICE_MESSAGE_TYPE_LEN] = b"e";
 pub const DEVICE_PARTIAL_MESSAGE: &[u8; DEVICE_MESSAGE_TYPE_LEN] = b"p";
 pub const DEVICE_OK_MESSAGE: &[u8; DEVICE_MESSAGE_TYPE_LEN] = b"k";
+pub const DEVICE_RESYNC_MESSAGE: &[u8; DEVICE_MESSAGE_TYPE_LEN] = b"r";
+/// The checksum of [`UPDATE_CHECKED_MESSAGE`] didn't match, the frame has to be sent again.
+pub const DEVICE_RESEND_MESSAGE: &[u8; DEVICE_MESSAGE_TYPE_LEN] = b"n";
 
 // https://pid.codes/1209/F0F0/
 // https://github.com/pidcodes/pidcodes.github.com/blob/9931091431d79f8e755b02fa1e34d4c279204a92/1209/F0F0/index.md
@@ -0,0 +1,67 @@
+/// Splits 8 bytes into their bits, the nth bit of every byte is combined into one byte.
+///
+/// Bit `k` of `out[n]` is bit `7 - n` of `i[k]`, so the most significant bits end up in the first
+/// byte. With one byte per strip this turns the colors of 8 strips into the bits that are shifted
+/// out in parallel, one output byte per bit time with a bit per strip.
+///
+/// This is an 8x8 bit matrix transpose done with two 32 bit halves, the RP2040 has no 64 bit
+/// registers. Each step swaps blocks of bits across the diagonal: single bits, 2x2 blocks and
+/// finally 4x4 blocks between the halves.
+///
+/// Panics if `out` is shorter than 8 bytes.
+#[inline]
+pub fn compress_byte(i: &[u8; 8], out: &mut [u8]) {
+	let mut lower = u32::from_le_bytes([i[0], i[1], i[2], i[3]]);
+	let mut upper = u32::from_le_bytes([i[4], i[5], i[6], i[7]]);
+
+	let t = (lower ^ (lower >> 7)) & 0x00_aa_00_aa;
+	lower ^= t ^ (t << 7);
+	let t = (upper ^ (upper >> 7)) & 0x00_aa_00_aa;
+	upper ^= t ^ (t << 7);
+
+	let t = (lower ^ (lower >> 14)) & 0x00_00_cc_cc;
+	lower ^= t ^ (t << 14);
+	let t = (upper ^ (upper >> 14)) & 0x00_00_cc_cc;
+	upper ^= t ^ (t << 14);
+
+	let t = (lower ^ (upper << 4)) & 0xf0_f0_f0_f0;
+	lower ^= t;
+	upper ^= t >> 4;
+
+	// the most significant bit ends up in the first byte
+	out[..4].copy_from_slice(&upper.to_be_bytes());
+	out[4..8].copy_from_slice(&lower.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The transpose one bit at a time, as the firmware did before.
+	fn compress_byte_per_bit(i: &[u8; 8]) -> [u8; 8] {
+		let mut out = [0; 8];
+		for (n, byte) in out.iter_mut().enumerate() {
+			for (k, input) in i.iter().enumerate() {
+				*byte |= ((input >> (7 - n)) & 1) << k;
+			}
+		}
+		out
+	}
+
+	#[test]
+	fn matches_per_bit_loop() {
+		// every value in every position, once with the other strips dark and once lit
+		for background in [0x00, 0xff, 0x5a] {
+			for position in 0..8 {
+				for value in 0..=u8::MAX {
+					let mut input = [background; 8];
+					input[position] = value;
+
+					let mut out = [0; 8];
+					compress_byte(&input, &mut out);
+					assert_eq!(out, compress_byte_per_bit(&input), "input {input:02x?}");
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use crate::Frame;
+
+/// An effect that draws one frame at a time, driven by `run_animation` on the controllers.
+///
+/// Closures taking the frame and the time are animations as well.
+pub trait Animation {
+	/// Draws the frame at `t`, the time since the animation started.
+	///
+	/// The frame still holds the previous frame, so effects can build on it.
+	fn render(&mut self, frame: &mut Frame, t: Duration);
+
+	/// Stops the animation once this returns `true`, it's checked before every frame.
+	fn is_finished(&self) -> bool {
+		false
+	}
+}
+
+impl<F: FnMut(&mut Frame, Duration)> Animation for F {
+	fn render(&mut self, frame: &mut Frame, t: Duration) {
+		self(frame, t)
+	}
+}
+
+/// The time between frames, no pacing for 0 fps.
+pub(crate) fn frame_interval(fps: u32) -> Duration {
+	if fps == 0 {
+		return Duration::ZERO;
+	}
+
+	Duration::from_secs(1) / fps
+}
@@ -0,0 +1,110 @@
+//! Command line tool to test the LEDs without writing a program.
+
+use clap::{Args, Parser, Subcommand};
+use serial_ws2812::{list_devices_detailed, Config, Frame, Result, SerialWs2812, HSV, RGB};
+
+#[derive(Parser)]
+#[command(version, about = "Control a Serial WS2812 device from the shell")]
+struct Cli {
+	#[command(subcommand)]
+	command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// List the connected devices.
+	List,
+	/// Set every LED to the same color.
+	Fill {
+		#[command(flatten)]
+		device: DeviceArgs,
+		/// The color as hex RRGGBB.
+		color:  RGB,
+	},
+	/// Run a rainbow across the strips until stopped.
+	Rainbow {
+		#[command(flatten)]
+		device: DeviceArgs,
+		/// How far the hue moves per frame.
+		#[arg(long, default_value_t = 1)]
+		speed:  u8,
+	},
+	/// Turn off every LED.
+	Clear {
+		#[command(flatten)]
+		device: DeviceArgs,
+	},
+}
+
+#[derive(Args)]
+struct DeviceArgs {
+	/// The serial device to use, the first device found if not set.
+	#[arg(long)]
+	device: Option<String>,
+	/// The amount of strips.
+	#[arg(long, default_value_t = 8)]
+	strips: usize,
+	/// The amount of LEDs per strip.
+	#[arg(long, default_value_t = 512)]
+	leds:   usize,
+}
+
+impl DeviceArgs {
+	fn open(self) -> Result<SerialWs2812> {
+		let config = Config::strips(self.strips, self.leds);
+
+		let mut controller = match self.device {
+			Some(device) => SerialWs2812::new(device, config)?,
+			None => SerialWs2812::find_required(config)?,
+		};
+		controller.configure()?;
+
+		Ok(controller)
+	}
+}
+
+fn main() -> Result<()> {
+	match Cli::parse().command {
+		Command::List => {
+			for device in list_devices_detailed()? {
+				match device.serial_number {
+					Some(serial_number) => println!("{} ({serial_number})", device.port_name),
+					None => println!("{}", device.port_name),
+				}
+			}
+		}
+		Command::Fill { device, color } => fill(device, color)?,
+		Command::Clear { device } => fill(device, RGB::default())?,
+		Command::Rainbow { device, speed } => rainbow(device, speed)?,
+	}
+
+	Ok(())
+}
+
+fn fill(device: DeviceArgs, color: RGB) -> Result<()> {
+	let mut controller = device.open()?;
+
+	let mut frame = Frame::new(controller.config());
+	frame.fill_gradient(color, color);
+	controller.send_leds(frame.as_bytes())?;
+
+	Ok(())
+}
+
+fn rainbow(device: DeviceArgs, speed: u8) -> Result<()> {
+	let mut controller = device.open()?;
+
+	let mut frame = Frame::new(controller.config());
+	let leds = frame.leds().max(1);
+	let mut hue: u8 = 0;
+
+	loop {
+		for (_, led, pixel) in frame.pixels_mut() {
+			let offset = (led * 256 / leds) as u8;
+			*pixel = HSV::new(hue.wrapping_add(offset), 255, 255).into();
+		}
+		controller.send_leds(frame.as_bytes())?;
+
+		hue = hue.wrapping_add(speed);
+	}
+}
@@ -0,0 +1,284 @@
+use std::str::FromStr;
+
+#[cfg(feature = "bytemuck")]
+use bytemuck::{Pod, Zeroable};
+use thiserror::Error;
+
+/// A single color, in the same layout as one LED in the buffers passed to the controller.
+///
+/// With the `bytemuck` feature `&[RGB]` can be cast to and from `&[u8]` without copying.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bytemuck", derive(Pod, Zeroable))]
+#[repr(C)]
+pub struct RGB {
+	pub r: u8,
+	pub g: u8,
+	pub b: u8,
+}
+
+impl RGB {
+	pub const fn new(r: u8, g: u8, b: u8) -> Self {
+		RGB { r, g, b }
+	}
+
+	/// The channels in the order of the LED buffers.
+	pub const fn to_array(&self) -> [u8; 3] {
+		[self.r, self.g, self.b]
+	}
+
+	/// Adds the channels of both colors, channels that would go over 255 stay at 255.
+	pub const fn saturating_add(self, other: RGB) -> RGB {
+		RGB::new(
+			self.r.saturating_add(other.r),
+			self.g.saturating_add(other.g),
+			self.b.saturating_add(other.b),
+		)
+	}
+}
+
+impl FromStr for RGB {
+	type Err = ParseColorError;
+
+	/// Parses a hex color like `ff8000`, optionally prefixed with `#`.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let hex = s.strip_prefix('#').unwrap_or(s);
+		if hex.len() != 6 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+			return Err(ParseColorError(s.to_owned()));
+		}
+
+		let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+		Ok(RGB::new(channel(0), channel(2), channel(4)))
+	}
+}
+
+/// A string that isn't a hex color.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid color {0:?}, expected a hex color like RRGGBB")]
+pub struct ParseColorError(String);
+
+impl From<RGB> for [u8; 3] {
+	fn from(rgb: RGB) -> Self {
+		rgb.to_array()
+	}
+}
+
+impl From<RGB> for (u8, u8, u8) {
+	fn from(rgb: RGB) -> Self {
+		(rgb.r, rgb.g, rgb.b)
+	}
+}
+
+impl From<(u8, u8, u8)> for RGB {
+	fn from(from: (u8, u8, u8)) -> Self {
+		RGB::new(from.0, from.1, from.2)
+	}
+}
+
+impl From<&(u8, u8, u8)> for RGB {
+	fn from(from: &(u8, u8, u8)) -> Self {
+		RGB::new(from.0, from.1, from.2)
+	}
+}
+
+impl From<[u8; 3]> for RGB {
+	fn from(from: [u8; 3]) -> Self {
+		RGB::new(from[0], from[1], from[2])
+	}
+}
+
+impl From<&[u8; 3]> for RGB {
+	fn from(from: &[u8; 3]) -> Self {
+		RGB::new(from[0], from[1], from[2])
+	}
+}
+
+/// A color as hue, saturation and value, with the hue going once around the color wheel over the
+/// whole `u8` range like in FastLED.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bytemuck", derive(Pod, Zeroable))]
+#[repr(C)]
+pub struct HSV {
+	pub hue:        u8,
+	pub saturation: u8,
+	pub value:      u8,
+}
+
+impl HSV {
+	pub const fn new(hue: u8, saturation: u8, value: u8) -> Self {
+		HSV {
+			hue,
+			saturation,
+			value,
+		}
+	}
+
+	/// Converts to RGB with FastLED's "rainbow" conversion, which gives yellow a bigger part of the
+	/// color wheel than a plain HSV to RGB conversion, so the hues look more evenly spaced on LEDs.
+	pub fn to_rgb(self) -> RGB {
+		hsv2rgb_rainbow(self)
+	}
+}
+
+impl From<HSV> for RGB {
+	fn from(hsv: HSV) -> Self {
+		hsv.to_rgb()
+	}
+}
+
+impl From<HSV> for [u8; 3] {
+	fn from(hsv: HSV) -> Self {
+		hsv.to_rgb().into()
+	}
+}
+
+/// The channel scale that leaves the colors as they are.
+pub(crate) const NO_CHANNEL_SCALE: [u8; 3] = [255; 3];
+
+/// Scales the red, green and blue channel of every LED, further channels like white are left as
+/// they are.
+pub(crate) fn scale_channels(leds: &mut [u8], bytes_per_led: usize, scale: [u8; 3]) {
+	for led in leds.chunks_exact_mut(bytes_per_led) {
+		for (channel, scale) in led.iter_mut().zip(scale) {
+			*channel = scale8(*channel, scale);
+		}
+	}
+}
+
+/// Converts 16 bit channels to 8 bit.
+///
+/// With `error` the part that is cut off is carried over to the same channel in the next frame, so
+/// the average over a few frames matches the 16 bit color. It is started over when the amount of
+/// pixels changes. Without it every channel is rounded to the closest 8 bit value.
+pub(crate) fn downscale_u16(pixels: &[[u16; 3]], error: Option<&mut Vec<u8>>) -> Vec<u8> {
+	let channels = pixels.iter().flatten();
+
+	let Some(error) = error else {
+		return channels
+			.map(|&channel| ((channel as u32 * 255 + 32767) / 65535) as u8)
+			.collect();
+	};
+
+	if error.len() != pixels.len() * 3 {
+		error.clear();
+		error.resize(pixels.len() * 3, 0);
+	}
+
+	channels
+		.zip(error.iter_mut())
+		.map(|(&channel, error)| {
+			let value = channel as u32 + *error as u32;
+			let out = (value >> 8).min(255);
+			*error = (value - (out << 8)).min(255) as u8;
+			out as u8
+		})
+		.collect()
+}
+
+// from fastled
+pub(crate) fn scale8(i: u8, scale: u8) -> u8 {
+	(((i as u16) * (1 + scale as u16)) >> 8) as u8
+}
+
+// from fastled
+fn scale8_video(i: u8, scale: u8) -> u8 {
+	(((i as usize * scale as usize) >> 8) + if i > 0 && scale > 0 { 1 } else { 0 }) as u8
+}
+
+// from fastled, with the moderate yellow boost and without scaling down green
+fn hsv2rgb_rainbow(hsv: HSV) -> RGB {
+	const K255: u8 = 255;
+	const K171: u8 = 171;
+	const K170: u8 = 170;
+	const K85: u8 = 85;
+
+	let hue = hsv.hue;
+	let sat = hsv.saturation;
+	let mut val = hsv.value;
+
+	// 0..31 scaled to 0..248
+	let offset8 = (hue & 0x1F) << 3;
+	let third = scale8(offset8, (256u16 / 3) as u8); // max = 85
+	let twothirds = scale8(offset8, ((256 * 2) / 3) as u8); // max = 170
+
+	let (mut r, mut g, mut b) = match hue >> 5 {
+		// R -> O
+		0 => (K255 - third, third, 0),
+		// O -> Y
+		1 => (K171, K85 + third, 0),
+		// Y -> G
+		2 => (K171 - twothirds, K170 + third, 0),
+		// G -> A
+		3 => (0, K255 - third, third),
+		// A -> B
+		4 => (0, K171 - twothirds, K85 + twothirds),
+		// B -> P
+		5 => (third, 0, K255 - third),
+		// P -> K
+		6 => (K85 + third, 0, K171 - third),
+		// K -> R
+		_ => (K170 + third, 0, K85 - third),
+	};
+
+	// scale down colors if we're desaturated at all and add the brightness floor to r, g, and b
+	if sat != 255 {
+		if sat == 0 {
+			(r, g, b) = (255, 255, 255);
+		} else {
+			r = scale8(r, sat);
+			g = scale8(g, sat);
+			b = scale8(b, sat);
+
+			let desat = 255 - sat;
+			let brightness_floor = scale8(desat, desat);
+			r += brightness_floor;
+			g += brightness_floor;
+			b += brightness_floor;
+		}
+	}
+
+	// now scale everything down if we're at value < 255
+	if val != 255 {
+		val = scale8_video(val, val);
+		if val == 0 {
+			(r, g, b) = (0, 0, 0);
+		} else {
+			r = scale8(r, val);
+			g = scale8(g, val);
+			b = scale8(b, val);
+		}
+	}
+
+	RGB::new(r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const COLOR: RGB = RGB::new(1, 2, 3);
+
+	#[test]
+	fn from_array() {
+		assert_eq!(RGB::from([1, 2, 3]), COLOR);
+		assert_eq!(RGB::from(&[1, 2, 3]), COLOR);
+	}
+
+	#[test]
+	fn from_tuple() {
+		assert_eq!(RGB::from((1, 2, 3)), COLOR);
+		assert_eq!(RGB::from(&(1, 2, 3)), COLOR);
+	}
+
+	#[test]
+	fn into_array_and_tuple() {
+		assert_eq!(<[u8; 3]>::from(COLOR), [1, 2, 3]);
+		assert_eq!(<(u8, u8, u8)>::from(COLOR), (1, 2, 3));
+	}
+
+	#[test]
+	fn to_array_is_in_channel_order() {
+		assert_eq!(COLOR.to_array(), [COLOR.r, COLOR.g, COLOR.b]);
+		assert_eq!(RGB::new(255, 0, 0).to_array(), [255, 0, 0]);
+		assert_eq!(RGB::new(0, 0, 255).to_array(), [0, 0, 255]);
+	}
+}
@@ -0,0 +1,366 @@
+use serial_ws2812_shared::{
+	BYTES_PER_LED,
+	COLOR_ORDER_BGR,
+	COLOR_ORDER_BRG,
+	COLOR_ORDER_GBR,
+	COLOR_ORDER_GRB,
+	COLOR_ORDER_RBG,
+	COLOR_ORDER_RGB,
+	FEATURE_COLOR_ORDER,
+	FEATURE_REVERSED,
+	FEATURE_STRIP_LENGTHS,
+	LED_TIMING_SK6812,
+	LED_TIMING_WS2811_400KHZ,
+	LED_TIMING_WS2812,
+	MAX_LEDS_PER_STRIP,
+	MAX_STRIPS,
+	PIXEL_FORMAT_RGB,
+};
+
+use crate::{rgbw, Error, Result, RgbwConversion};
+
+/// The amount of strips and LEDs per strip that are connected to the device.
+///
+/// At least 1 strip with 1 LED is required.
+///
+/// Created with [`Self::strips`], [`Self::single_strip`] or [`Self::matrix`] and the `with_*`
+/// methods, new settings can be added without a breaking change.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Config {
+	pub strips:        usize,
+	pub leds:          usize,
+	/// LEDs per strip for strips with different lengths, overrides `leds`.
+	///
+	/// Only the first `strips` entries are used, the LED data for every strip is then only as long as
+	/// the strip itself. Requires a device with [`FEATURE_STRIP_LENGTHS`].
+	pub strip_lengths: Option<[u16; MAX_STRIPS]>,
+	/// Strips that are wired backwards, the device flips their LEDs when copying the data so the
+	/// first LED in the buffer is still the one at the start of the strip.
+	///
+	/// Requires a device with [`FEATURE_REVERSED`] if any strip is reversed.
+	pub reversed:      [bool; MAX_STRIPS],
+	/// The order every strip expects the color channels in, for strips from different vendors on
+	/// the same device. The LED data is always red, green, blue.
+	///
+	/// Requires a device with [`FEATURE_COLOR_ORDER`] if any strip isn't [`ColorOrder::Grb`], which
+	/// isn't supported with RGBW strips.
+	pub color_orders:  [ColorOrder; MAX_STRIPS],
+	/// Drive RGBW strips, the white channel is computed according to the conversion.
+	///
+	/// All strips have to be RGBW, reversed strips are not supported.
+	pub rgbw_mode:     Option<RgbwConversion>,
+	/// The order of the LEDs in the buffers passed to `send_leds`, they are reordered to what the
+	/// device expects before sending.
+	pub buffer_layout: BufferLayout,
+}
+
+impl Config {
+	/// One strip with `leds` LEDs.
+	pub fn single_strip(leds: usize) -> Self {
+		Self::strips(1, leds)
+	}
+
+	/// `strips` strips that all have `leds` LEDs.
+	pub fn strips(strips: usize, leds: usize) -> Self {
+		Self {
+			strips,
+			leds,
+			..Default::default()
+		}
+	}
+
+	/// A matrix panel with `width` by `height` LEDs, split into as few strips as possible with every
+	/// strip driving whole rows.
+	///
+	/// The rows are spread evenly over the strips, if that doesn't work out the last strip is
+	/// shorter, which requires a device with [`FEATURE_STRIP_LENGTHS`]. Use [`crate::Layout`] with
+	/// the same size to address the LEDs by position.
+	pub fn matrix(width: usize, height: usize) -> Self {
+		let max_rows = (MAX_LEDS_PER_STRIP / width.max(1)).max(1);
+		let strips = height.div_ceil(max_rows).max(1);
+		let rows = height.div_ceil(strips);
+		let strips = height.div_ceil(rows.max(1)).max(1);
+
+		let last_rows = height - rows * (strips - 1);
+		if last_rows == rows {
+			return Self::strips(strips, rows * width);
+		}
+
+		let mut lengths = [0; MAX_STRIPS];
+		for (strip, len) in lengths.iter_mut().enumerate().take(strips) {
+			let strip_rows = if strip == strips - 1 { last_rows } else { rows };
+			*len = u16::try_from(strip_rows * width).unwrap_or(u16::MAX);
+		}
+
+		Self {
+			strip_lengths: Some(lengths),
+			..Self::strips(strips, rows * width)
+		}
+	}
+
+	/// Sets [`Self::strip_lengths`].
+	pub fn with_strip_lengths(self, strip_lengths: [u16; MAX_STRIPS]) -> Self {
+		Self {
+			strip_lengths: Some(strip_lengths),
+			..self
+		}
+	}
+
+	/// Sets [`Self::reversed`].
+	pub fn with_reversed(self, reversed: [bool; MAX_STRIPS]) -> Self {
+		Self { reversed, ..self }
+	}
+
+	/// Sets [`Self::color_orders`].
+	pub fn with_color_orders(self, color_orders: [ColorOrder; MAX_STRIPS]) -> Self {
+		Self {
+			color_orders,
+			..self
+		}
+	}
+
+	/// Sets [`Self::rgbw_mode`].
+	pub fn with_rgbw_mode(self, conversion: RgbwConversion) -> Self {
+		Self {
+			rgbw_mode: Some(conversion),
+			..self
+		}
+	}
+
+	/// Sets [`Self::buffer_layout`].
+	pub fn with_buffer_layout(self, buffer_layout: BufferLayout) -> Self {
+		Self {
+			buffer_layout,
+			..self
+		}
+	}
+
+	/// The amount of LEDs on a strip, strips that aren't configured have none.
+	pub fn strip_len(&self, strip: usize) -> usize {
+		if strip >= self.strips {
+			return 0;
+		}
+
+		match &self.strip_lengths {
+			Some(lengths) => lengths.get(strip).map_or(0, |&len| len as usize),
+			None => self.leds,
+		}
+	}
+
+	/// The amount of LEDs of every configured strip.
+	pub(crate) fn strip_lens(&self) -> impl Iterator<Item = usize> + '_ {
+		(0..self.strips).map(|strip| self.strip_len(strip))
+	}
+
+	/// Bitmask of the reversed strips, as sent to the device.
+	pub(crate) fn reversed_mask(&self) -> u32 {
+		self.reversed
+			.iter()
+			.enumerate()
+			.filter(|(_, &reversed)| reversed)
+			.fold(0, |mask, (strip, _)| mask | 1 << strip)
+	}
+
+	/// The amount of LEDs per strip the device is configured with, more than the actual LEDs for
+	/// RGBW strips.
+	pub(crate) fn device_strip_lens(&self) -> impl Iterator<Item = usize> + '_ {
+		self.strip_lens().map(|len| match self.rgbw_mode {
+			Some(_) => rgbw::device_strip_len(len),
+			None => len,
+		})
+	}
+
+	/// The amount of bytes per LED in the buffers passed to the controller, 4 for RGBW strips
+	/// without conversion.
+	pub fn bytes_per_led(&self) -> usize {
+		self.rgbw_mode
+			.map_or(BYTES_PER_LED, |conversion| conversion.bytes_per_led())
+	}
+
+	/// The amount of LEDs on all strips together, with [`Self::strip_lengths`] if they are set.
+	pub fn leds_total(&self) -> usize {
+		self.strip_lens().sum()
+	}
+
+	/// The amount of bytes in a full update, `send_leds` expects a buffer of exactly this size.
+	pub fn buffer_size(&self) -> usize {
+		self.leds_total() * self.bytes_per_led()
+	}
+
+	/// The amount of bytes in a full update with 3 bytes per LED, the size of a [`crate::Frame`].
+	pub(crate) fn rgb_buffer_size(&self) -> usize {
+		self.leds_total() * BYTES_PER_LED
+	}
+
+	/// The amount of LED data sent to the device for every frame, without the command.
+	///
+	/// The same as [`Self::buffer_size`], except for RGBW strips, which are packed into more LEDs
+	/// for the device.
+	pub fn frame_bytes(&self) -> usize {
+		self.device_strip_lens().sum::<usize>() * BYTES_PER_LED
+	}
+
+	/// The `COLOR_ORDER_*` value of every strip, as sent to the device.
+	pub(crate) fn color_order_ids(&self) -> [u8; MAX_STRIPS] {
+		self.color_orders.map(ColorOrder::id)
+	}
+
+	/// Checks that the device is able to handle the config.
+	pub fn validate(&self, capabilities: &Capabilities) -> Result<()> {
+		if self.strips == 0 || self.strip_lens().any(|len| len == 0) {
+			return Err(Error::InvalidConfig(
+				"at least 1 strip and 1 led per strip are required".to_string(),
+			));
+		}
+
+		if self.strips > capabilities.max_strips {
+			return Err(Error::InvalidConfig(format!(
+				"{} strips configured, but the device supports at most {}",
+				self.strips, capabilities.max_strips
+			)));
+		}
+
+		let leds = self.device_strip_lens().max().unwrap_or(0);
+		if leds > capabilities.max_leds_per_strip {
+			return Err(Error::InvalidConfig(format!(
+				"{} leds per strip configured, but the device supports at most {}",
+				leds, capabilities.max_leds_per_strip
+			)));
+		}
+
+		if self.strip_lengths.is_some() && !capabilities.has_feature(FEATURE_STRIP_LENGTHS) {
+			return Err(Error::InvalidConfig(
+				"the device does not support strips with different lengths".to_string(),
+			));
+		}
+
+		if self.reversed_mask() != 0 && !capabilities.has_feature(FEATURE_REVERSED) {
+			return Err(Error::InvalidConfig(
+				"the device does not support reversed strips".to_string(),
+			));
+		}
+
+		if self.rgbw_mode.is_some() && self.reversed_mask() != 0 {
+			return Err(Error::InvalidConfig(
+				"reversed strips are not supported with rgbw".to_string(),
+			));
+		}
+
+		let custom_color_order = self
+			.color_orders
+			.iter()
+			.any(|&order| order != ColorOrder::Grb);
+		if custom_color_order && !capabilities.has_feature(FEATURE_COLOR_ORDER) {
+			return Err(Error::InvalidConfig(
+				"the device does not support color orders".to_string(),
+			));
+		}
+
+		if custom_color_order && self.rgbw_mode.is_some() {
+			return Err(Error::InvalidConfig(
+				"color orders are not supported with rgbw".to_string(),
+			));
+		}
+
+		Ok(())
+	}
+}
+
+/// The limits and features of a device, as reported by the firmware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+	pub max_strips:         usize,
+	pub max_leds_per_strip: usize,
+	/// Bitset of the supported `PIXEL_FORMAT_*` values.
+	pub pixel_formats:      u32,
+	/// Bitset of the supported `FEATURE_*` values.
+	pub features:           u32,
+}
+
+impl Capabilities {
+	/// Used for firmware that predates the capabilities command.
+	pub const LEGACY: Self = Self {
+		max_strips:         MAX_STRIPS,
+		max_leds_per_strip: MAX_LEDS_PER_STRIP,
+		pixel_formats:      PIXEL_FORMAT_RGB,
+		features:           0,
+	};
+
+	pub fn supports_pixel_format(&self, pixel_format: u32) -> bool {
+		self.pixel_formats & pixel_format == pixel_format
+	}
+
+	pub fn has_feature(&self, feature: u32) -> bool {
+		self.features & feature == feature
+	}
+}
+
+/// The order of the LEDs in a buffer, see [`Config::buffer_layout`].
+///
+/// Every LED takes up [`Config::bytes_per_led`] bytes in both layouts and the buffers have the same
+/// size, only the order of the LEDs differs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BufferLayout {
+	/// All LEDs of strip 0 from the start of the strip, followed by all LEDs of strip 1 and so on,
+	/// every strip takes up as many LEDs as it is long. LED `led` of strip `strip` starts at byte
+	/// `(len(0) + ... + len(strip - 1) + led) * bytes_per_led`.
+	///
+	/// This is what the device expects, so the buffer is sent as it is.
+	#[default]
+	StripMajor,
+	/// LED 0 of every strip, followed by LED 1 of every strip and so on, strips that are shorter
+	/// than the LED are left out. With strips of the same length LED `led` of strip `strip` starts
+	/// at byte `(led * strips + strip) * bytes_per_led`.
+	LedMajor,
+}
+
+/// The order a strip expects the color channels in, named by what is sent first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorOrder {
+	/// Green, red, blue like the WS2812 and most other strips.
+	#[default]
+	Grb,
+	Rgb,
+	Brg,
+	Rbg,
+	Gbr,
+	Bgr,
+}
+
+impl ColorOrder {
+	/// The `COLOR_ORDER_*` value sent to the device.
+	pub(crate) fn id(self) -> u8 {
+		match self {
+			ColorOrder::Grb => COLOR_ORDER_GRB,
+			ColorOrder::Rgb => COLOR_ORDER_RGB,
+			ColorOrder::Brg => COLOR_ORDER_BRG,
+			ColorOrder::Rbg => COLOR_ORDER_RBG,
+			ColorOrder::Gbr => COLOR_ORDER_GBR,
+			ColorOrder::Bgr => COLOR_ORDER_BGR,
+		}
+	}
+}
+
+/// The bit timing of the LED chips.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LedTiming {
+	/// WS2812 and WS2812B at 800kHz.
+	#[default]
+	Ws2812,
+	/// SK6812 at 800kHz with shorter high pulses, also for WS2811 in high speed mode.
+	Sk6812,
+	/// WS2811 in low speed mode at 400kHz.
+	Ws2811Slow,
+}
+
+impl LedTiming {
+	/// The `LED_TIMING_*` value sent to the device.
+	pub(crate) fn id(self) -> u32 {
+		match self {
+			LedTiming::Ws2812 => LED_TIMING_WS2812,
+			LedTiming::Sk6812 => LED_TIMING_SK6812,
+			LedTiming::Ws2811Slow => LED_TIMING_WS2811_400KHZ,
+		}
+	}
+}
@@ -0,0 +1,1229 @@
+//! The controller logic shared by the blocking and the tokio backend.
+//!
+//! [`Connection`] is written once with `async` methods over a [`Transport`], which is the only part
+//! the backends provide. The tokio controller awaits the methods, the blocking one runs them with
+//! [`block_on`], its transport blocks the thread instead of ever returning [`Poll::Pending`].
+
+use std::{
+	future::Future,
+	io,
+	mem,
+	pin::pin,
+	sync::Arc,
+	task::{Context, Poll, Wake, Waker},
+	time::{Duration, Instant},
+};
+
+use serial_ws2812_shared::{
+	Command,
+	BOOTLOADER_MAGIC,
+	CAPABILITIES_LEN,
+	CONFIG_LEN,
+	DEVICE_ID_LEN,
+	DEVICE_MESSAGE_TYPE_LEN,
+	DEVICE_OK_MESSAGE,
+	DIFF_MESSAGE,
+	FEATURE_DEVICE_ID,
+	FEATURE_LENGTH_PREFIX,
+	FEATURE_PING,
+	FEATURE_RLE_UPDATE,
+	FEATURE_TELEMETRY,
+	FEATURE_UPDATE_CHECKSUM,
+	FRAMING_LENGTH_PREFIXED,
+	GET_CAPABILITIES_MESSAGE,
+	GET_CONFIG_MESSAGE,
+	GET_ID_MESSAGE,
+	GET_TELEMETRY_MESSAGE,
+	MAX_RESET_DURATION_US,
+	MAX_STRIPS,
+	MAX_VENDOR_DATA_LEN,
+	MESSAGE_TYPE_LEN,
+	MIN_RESET_DURATION_US,
+	PING_MESSAGE,
+	REBOOT_TO_BOOTLOADER_MESSAGE,
+	RESYNC_MESSAGE,
+	SET_FRAMING_MESSAGE,
+	SET_LED_TIMING_MESSAGE,
+	SET_RESET_DURATION_MESSAGE,
+	SET_STRIP_ENABLE_MESSAGE,
+	STREAM_END_MESSAGE,
+	STREAM_FRAME_MESSAGE,
+	STREAM_MESSAGE,
+	TELEMETRY_LEN,
+	VENDOR_ID_LEN,
+	WAIT_DISPLAYED_MESSAGE,
+};
+use serialport::{ClearBuffer, SerialPort};
+#[cfg(feature = "tracing")]
+use tracing::instrument;
+
+use crate::{
+	animation::{self, Animation},
+	color::{downscale_u16, NO_CHANNEL_SCALE},
+	frame::checksum,
+	log::{debug, info, trace, warn},
+	protocol::{
+		self,
+		changed_config_commands,
+		check_response,
+		config_commands,
+		decode_capabilities,
+		decode_config,
+		decode_telemetry,
+		encode_diff,
+		encode_leds,
+		find_device,
+		flat_to_strip_major,
+		is_frame_command,
+		needs_encoding,
+		update_command,
+		ConfigCommand,
+		Exchange,
+		Recovery,
+		Reset,
+		ResponseRead,
+		Retry,
+		Step,
+		Stream,
+		ALL_STRIPS,
+	},
+	self_test::{self, ColorResults, COLOR_DURATION, FPS_FRAMES, TEST_COLORS},
+	stats::{self, Clock, ThroughputReport},
+	BufferLayout,
+	Capabilities,
+	ColorOrder,
+	Config,
+	ControllerStats,
+	Error,
+	Frame,
+	LedTiming,
+	Result,
+	SelfTestReport,
+	Telemetry,
+	WriteResult,
+	RGB,
+};
+
+/// How a backend talks to the serial port, everything else is up to the [`Connection`].
+pub(crate) trait Transport: Sized {
+	/// The port underneath, for its settings and control lines.
+	type Port: SerialPort + ?Sized;
+
+	/// Opens the port with the settings of [`crate::SerialWs2812::new`].
+	fn open(serial_device: &str, baud_rate: u32, exclusive: bool) -> Result<Self>;
+
+	/// Opens the port again in place of the current one, for reconnects.
+	fn reopen(&mut self, serial_device: &str, baud_rate: u32, exclusive: bool) -> Result<()>;
+
+	fn port(&mut self) -> &mut Self::Port;
+
+	async fn write_all(&mut self, buffer: &[u8]) -> io::Result<()>;
+
+	/// Reads what the device sent, fails with [`io::ErrorKind::TimedOut`] if nothing arrived within
+	/// `timeout`. Blocking ports wait for the timeout set on the port instead.
+	async fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> io::Result<usize>;
+
+	/// Like [`Self::read`], but has to fill `buffer`.
+	async fn read_exact(&mut self, buffer: &mut [u8], timeout: Duration) -> io::Result<()>;
+
+	async fn sleep(duration: Duration);
+}
+
+/// Runs a future of the blocking backend, its transport finishes every read and write before it
+/// returns, so the future is done after a single poll.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+	struct NoopWaker;
+
+	impl Wake for NoopWaker {
+		fn wake(self: Arc<Self>) {}
+	}
+
+	let waker = Waker::from(Arc::new(NoopWaker));
+	match pin!(future).poll(&mut Context::from_waker(&waker)) {
+		Poll::Ready(output) => output,
+		Poll::Pending => unreachable!("the blocking transport never returns pending"),
+	}
+}
+
+/// The state of a controller and everything it does with the device, see the module docs.
+///
+/// The public methods are documented on the controllers.
+pub(crate) struct Connection<T> {
+	config:          Config,
+	pub(crate) port: T,
+
+	initialized:  bool,
+	configured:   bool,
+	/// The config commands that were last sent to the device.
+	sent_config:  Vec<ConfigCommand>,
+	capabilities: Option<Capabilities>,
+	stream:       Option<Stream>,
+	/// The commands are length-prefixed, see [`FRAMING_LENGTH_PREFIXED`].
+	framed:       bool,
+
+	/// The name the port was opened with, to open it again on reconnects.
+	serial_device:     Option<String>,
+	/// Whether the port is opened for exclusive access, kept for reconnects.
+	exclusive:         bool,
+	/// See [`Self::set_configure_on_reconnect`].
+	reconfigure:       bool,
+	/// Kept for reconnects, see [`Self::probe_baud_rates`].
+	baud_rate:         u32,
+	stats:             ControllerStats,
+	/// Bitmask of the enabled strips.
+	enabled_strips:    u32,
+	/// Only set if it was changed from the default.
+	reset_duration_us: Option<u32>,
+	led_timing:        LedTiming,
+	/// Multiplier for the red, green and blue channel, see [`Self::set_channel_scale`].
+	channel_scale:     [u8; 3],
+	/// How often a frame is sent again after a resync, see [`Self::set_frame_retries`].
+	frame_retries:     usize,
+	/// See [`Self::set_blank_on_error`].
+	blank_on_error:    bool,
+	/// See [`Self::set_update_checksums`].
+	update_checksums:  bool,
+	/// See [`Self::set_rle_updates`].
+	rle_updates:       bool,
+	/// See [`Self::set_skip_unchanged`].
+	skip_unchanged:    bool,
+	/// Checksum of the last frame sent with [`Self::send_leds`], cleared by every other command.
+	last_checksum:     Option<u64>,
+	/// What [`Self::send_leds_u16`] cut off in the last frame, `None` if dithering is disabled.
+	dither_error:      Option<Vec<u8>>,
+	/// Reused for the LEDs when they have to be changed before sending, see [`Self::encode_leds`].
+	scratch:           Vec<u8>,
+	/// Reused for the data of checked and run-length encoded updates, see
+	/// [`Self::send_update`].
+	update_data:       Vec<u8>,
+	/// Measures the timings of commands, see [`Self::set_clock`].
+	clock:             Clock,
+}
+
+impl<T: Transport> Connection<T> {
+	pub(crate) fn new(port: T, config: Config) -> Self {
+		Self {
+			config,
+			port,
+
+			initialized: false,
+			configured: false,
+			sent_config: Vec::new(),
+			capabilities: None,
+			stream: None,
+			framed: false,
+
+			serial_device: None,
+			exclusive: true,
+			baud_rate: protocol::BAUD_RATE,
+			stats: ControllerStats::default(),
+			enabled_strips: ALL_STRIPS,
+			reset_duration_us: None,
+			led_timing: LedTiming::default(),
+			channel_scale: NO_CHANNEL_SCALE,
+			frame_retries: 0,
+			blank_on_error: false,
+			reconfigure: true,
+			update_checksums: false,
+			rle_updates: false,
+			skip_unchanged: false,
+			last_checksum: None,
+			dither_error: Some(Vec::new()),
+			scratch: Vec::new(),
+			update_data: Vec::new(),
+			clock: stats::default_clock(),
+		}
+	}
+
+	pub(crate) fn open(
+		serial_device: String,
+		config: Config,
+		baud_rate: u32,
+		exclusive: bool,
+	) -> Result<Self> {
+		let port = T::open(&serial_device, baud_rate, exclusive)?;
+
+		Ok(Self {
+			serial_device: Some(serial_device),
+			exclusive,
+			baud_rate,
+			..Self::new(port, config)
+		})
+	}
+
+	pub(crate) async fn probe_baud_rates(
+		serial_device: &str,
+		candidates: &[u32],
+		config: Config,
+	) -> Result<Self> {
+		let mut last_error = Error::NoBaudRates;
+
+		for &baud_rate in candidates {
+			// adapters can refuse rates they don't support, the next one might still work
+			let mut connection =
+				match Self::open(serial_device.to_owned(), config.clone(), baud_rate, true) {
+					Ok(connection) => connection,
+					Err(e) => {
+						warn!("{serial_device} can't be opened at {baud_rate} baud: {e}");
+						last_error = e;
+						continue;
+					}
+				};
+			match connection.probe().await {
+				Ok(()) => {
+					info!("{serial_device} answered at {baud_rate} baud");
+					return Ok(connection);
+				}
+				Err(e) => {
+					warn!("{serial_device} didn't answer at {baud_rate} baud: {e}");
+					last_error = e;
+				}
+			}
+		}
+
+		Err(last_error)
+	}
+
+	pub(crate) fn baud_rate(&self) -> u32 {
+		self.baud_rate
+	}
+
+	/// Resets the device with a short timeout and queries its capabilities.
+	async fn probe(&mut self) -> Result<()> {
+		self.reset_with(Reset::probe()).await?;
+		self.initialized = true;
+		self.capabilities().await?;
+
+		Ok(())
+	}
+
+	async fn reset_to_command(&mut self) -> Result<()> {
+		self.reset_with(Reset::default()).await
+	}
+
+	async fn reset_with(&mut self, mut reset: Reset) -> Result<()> {
+		let mut buffer = [0u8; Reset::BUFFER_LEN];
+
+		info!("trying to reset device to start of command");
+		self.port.port().set_timeout(protocol::RESET_TIMEOUT)?;
+		// stale responses must not be mistaken for the response to the resync
+		self.port.port().clear(ClearBuffer::Input)?;
+		self.port.write_all(RESYNC_MESSAGE).await?;
+
+		loop {
+			let res = self.port.read(&mut buffer, protocol::RESET_TIMEOUT).await;
+			let read_bytes = match res {
+				Ok(n) => n,
+				Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+					let Some(bytes) = reset.on_timeout() else {
+						self.port.port().set_timeout(protocol::TIMEOUT)?;
+						return Err(protocol::reset_failed(self.port.port().name()));
+					};
+					self.port.write_all(bytes).await?;
+					continue;
+				}
+				Err(e) => return Err(e.into()),
+			};
+
+			if reset.on_read(&buffer[..read_bytes]) {
+				break;
+			}
+		}
+
+		self.port.port().set_timeout(protocol::TIMEOUT)?;
+		self.stream = None;
+		// the device leaves framed mode on a resync, or when it rejects the null bytes
+		self.framed = false;
+		info!("reset successful");
+
+		Ok(())
+	}
+
+	pub(crate) fn config(&self) -> &Config {
+		&self.config
+	}
+
+	pub(crate) async fn set_config(&mut self, config: Config) -> Result<()> {
+		self.config = config;
+		// the same bytes might be a different frame with another layout
+		self.last_checksum = None;
+		self.ensure_configured().await
+	}
+
+	pub(crate) async fn configure(&mut self) -> Result<()> {
+		self.initialize().await?;
+
+		let capabilities = self.capabilities().await?;
+		self.config.validate(&capabilities)?;
+
+		if capabilities.has_feature(FEATURE_LENGTH_PREFIX) && !self.framed {
+			self.send_command(SET_FRAMING_MESSAGE, &FRAMING_LENGTH_PREFIXED.to_le_bytes())
+				.await?;
+			self.framed = true;
+		}
+
+		let commands = config_commands(&self.config);
+		for (command, data) in &commands {
+			self.send_command(command, data).await?;
+		}
+		self.sent_config = commands;
+		if self.enabled_strips != ALL_STRIPS {
+			self.send_command(SET_STRIP_ENABLE_MESSAGE, &self.enabled_strips.to_le_bytes())
+				.await?;
+		}
+		if self.led_timing != LedTiming::default() {
+			self.send_command(SET_LED_TIMING_MESSAGE, &self.led_timing.id().to_le_bytes())
+				.await?;
+		}
+		if let Some(micros) = self.reset_duration_us {
+			self.send_command(SET_RESET_DURATION_MESSAGE, &micros.to_le_bytes())
+				.await?;
+		}
+		self.configured = true;
+
+		Ok(())
+	}
+
+	pub(crate) async fn ensure_configured(&mut self) -> Result<()> {
+		if !self.configured {
+			return self.configure().await;
+		}
+
+		// a config that doesn't reach the device must not be used for frames, they would have a
+		// different size than the device expects, the next frame configures it from scratch instead
+		self.configured = false;
+
+		let capabilities = self.capabilities().await?;
+		self.config.validate(&capabilities)?;
+
+		let commands = config_commands(&self.config);
+		for (command, data) in changed_config_commands(&self.sent_config, &commands) {
+			self.send_command(command, data).await?;
+		}
+		self.sent_config = commands;
+		self.configured = true;
+
+		Ok(())
+	}
+
+	pub(crate) async fn configure_with_retries(
+		&mut self,
+		attempts: usize,
+		backoff: Duration,
+	) -> Result<()> {
+		let mut attempt = 1;
+		loop {
+			match self.configure().await {
+				Ok(()) => return Ok(()),
+				Err(e) if attempt >= attempts => return Err(e),
+				Err(e) => warn!("configuring failed (attempt {attempt} of {attempts}): {e}"),
+			}
+
+			self.initialized = false;
+			self.configured = false;
+			self.capabilities = None;
+			self.stream = None;
+
+			T::sleep(backoff).await;
+			attempt += 1;
+		}
+	}
+
+	pub(crate) async fn capabilities(&mut self) -> Result<Capabilities> {
+		if let Some(capabilities) = self.capabilities {
+			return Ok(capabilities);
+		}
+
+		self.initialize().await?;
+
+		let mut response = [0u8; CAPABILITIES_LEN];
+		let capabilities = match self.query(GET_CAPABILITIES_MESSAGE, &mut response).await {
+			Ok(()) => decode_capabilities(&response),
+			Err(Error::UnsupportedCommand) => Capabilities::LEGACY,
+			Err(e) => return Err(e),
+		};
+		self.capabilities = Some(capabilities);
+
+		Ok(capabilities)
+	}
+
+	pub(crate) async fn telemetry(&mut self) -> Result<Telemetry> {
+		self.initialize().await?;
+
+		let mut response = [0u8; TELEMETRY_LEN];
+		self.query(GET_TELEMETRY_MESSAGE, &mut response).await?;
+
+		Ok(decode_telemetry(&response))
+	}
+
+	pub(crate) async fn device_id(&mut self) -> Result<[u8; DEVICE_ID_LEN]> {
+		self.initialize().await?;
+
+		let mut response = [0u8; DEVICE_ID_LEN];
+		self.query(GET_ID_MESSAGE, &mut response).await?;
+
+		Ok(response)
+	}
+
+	pub(crate) async fn load_config_from_device(&mut self) -> Result<Config> {
+		self.initialize().await?;
+
+		let mut response = [0u8; CONFIG_LEN];
+		self.query(GET_CONFIG_MESSAGE, &mut response).await?;
+
+		let config = decode_config(&response);
+		// the device already has it, so it doesn't have to be sent again
+		self.sent_config = config_commands(&config);
+		self.config = config.clone();
+		self.last_checksum = None;
+
+		Ok(config)
+	}
+
+	pub(crate) async fn measure_throughput(&mut self, frames: usize) -> Result<ThroughputReport> {
+		let frame_bytes = self.transfer_size();
+		let mut frame_times = Vec::with_capacity(frames);
+
+		for frame in 0..frames {
+			let leds = vec![(frame % 16) as u8; frame_bytes];
+
+			let start = (self.clock)();
+			self.send_leds(&leds).await?;
+			frame_times.push((self.clock)() - start);
+		}
+
+		Ok(ThroughputReport::new(&frame_times, frame_bytes))
+	}
+
+	pub(crate) async fn self_test(&mut self) -> Result<SelfTestReport> {
+		self.initialized = false;
+		self.configured = false;
+		self.capabilities = None;
+		self.configure().await?;
+
+		let capabilities = self.capabilities().await?;
+		let device_id = match capabilities.has_feature(FEATURE_DEVICE_ID) {
+			true => Some(self.device_id().await?),
+			false => None,
+		};
+		let telemetry = match capabilities.has_feature(FEATURE_TELEMETRY) {
+			true => Some(self.telemetry().await?),
+			false => None,
+		};
+		let round_trip = match capabilities.has_feature(FEATURE_PING) {
+			true => {
+				let start = (self.clock)();
+				self.ping().await?;
+				Some((self.clock)() - start)
+			}
+			false => None,
+		};
+
+		// every frame has to be sent, even if it is the same as the last one
+		let skip_unchanged = mem::replace(&mut self.skip_unchanged, false);
+		let frames = self.self_test_frames().await;
+		self.skip_unchanged = skip_unchanged;
+		let (colors, fps) = frames?;
+
+		Ok(SelfTestReport {
+			capabilities,
+			device_id,
+			telemetry,
+			round_trip,
+			colors,
+			fps,
+		})
+	}
+
+	async fn self_test_frames(&mut self) -> Result<(ColorResults, Option<f32>)> {
+		let mut colors = TEST_COLORS.map(|color| (color, false));
+		for (color, acked) in &mut colors {
+			*acked = self
+				.send_leds(&self_test::fill(&self.config, *color))
+				.await
+				.is_ok();
+			if !*acked {
+				self.reset_to_command().await?;
+			}
+			T::sleep(COLOR_DURATION).await;
+		}
+
+		let black = self_test::fill(&self.config, RGB::default());
+		let start = (self.clock)();
+		let mut sent = 0;
+		while sent < FPS_FRAMES && self.send_leds(&black).await.is_ok() {
+			sent += 1;
+		}
+
+		let fps = if sent == FPS_FRAMES {
+			Some(FPS_FRAMES as f32 / ((self.clock)() - start).as_secs_f32())
+		} else {
+			self.reset_to_command().await?;
+			None
+		};
+
+		Ok((colors, fps))
+	}
+
+	pub(crate) async fn ping(&mut self) -> Result<()> {
+		self.initialize().await?;
+
+		self.query(PING_MESSAGE, &mut []).await
+	}
+
+	pub(crate) fn transfer_size(&self) -> usize {
+		self.config.buffer_size()
+	}
+
+	pub(crate) async fn wait_until_displayed(&mut self) -> Result<()> {
+		self.initialize().await?;
+
+		self.query(WAIT_DISPLAYED_MESSAGE, &mut []).await
+	}
+
+	pub(crate) async fn reconnect(&mut self) -> Result<()> {
+		let serial_device = match &self.serial_device {
+			Some(serial_device) => serial_device.clone(),
+			None => find_device()?.ok_or(Error::DeviceNotFound)?,
+		};
+
+		info!("reconnecting to {serial_device}");
+		self.port
+			.reopen(&serial_device, self.baud_rate, self.exclusive)?;
+		self.initialized = false;
+		self.configured = false;
+		self.capabilities = None;
+		self.stream = None;
+		self.last_checksum = None;
+		self.stats.reconnects += 1;
+
+		if self.reconfigure {
+			self.configure().await?;
+		}
+
+		Ok(())
+	}
+
+	pub(crate) fn set_configure_on_reconnect(&mut self, configure: bool) {
+		self.reconfigure = configure;
+	}
+
+	pub(crate) fn stats(&self) -> ControllerStats {
+		self.stats
+	}
+
+	pub(crate) fn reset_stats(&mut self) {
+		self.stats = ControllerStats::default();
+	}
+
+	pub(crate) fn set_clock(&mut self, clock: impl Fn() -> Duration + Send + 'static) {
+		self.clock = Box::new(clock);
+	}
+
+	pub(crate) fn set_channel_scale(&mut self, r: u8, g: u8, b: u8) {
+		self.channel_scale = [r, g, b];
+		self.last_checksum = None;
+	}
+
+	pub(crate) fn set_skip_unchanged(&mut self, skip: bool) {
+		self.skip_unchanged = skip;
+		self.last_checksum = None;
+	}
+
+	pub(crate) fn set_dithering(&mut self, enabled: bool) {
+		self.dither_error = enabled.then(Vec::new);
+	}
+
+	pub(crate) fn set_frame_retries(&mut self, retries: usize) {
+		self.frame_retries = retries;
+	}
+
+	pub(crate) fn set_blank_on_error(&mut self, blank: bool) {
+		self.blank_on_error = blank;
+	}
+
+	pub(crate) async fn set_update_checksums(&mut self, enabled: bool) -> Result<()> {
+		if enabled
+			&& !self
+				.capabilities()
+				.await?
+				.has_feature(FEATURE_UPDATE_CHECKSUM)
+		{
+			return Err(Error::UnsupportedCommand);
+		}
+
+		self.update_checksums = enabled;
+		Ok(())
+	}
+
+	pub(crate) async fn set_rle_updates(&mut self, enabled: bool) -> Result<()> {
+		if enabled && !self.capabilities().await?.has_feature(FEATURE_RLE_UPDATE) {
+			return Err(Error::UnsupportedCommand);
+		}
+
+		self.rle_updates = enabled;
+		Ok(())
+	}
+
+	pub(crate) async fn set_strip_enabled(&mut self, strip: usize, enabled: bool) -> Result<()> {
+		if strip >= MAX_STRIPS {
+			return Err(Error::InvalidConfig(format!(
+				"strip {strip} out of range (0..{MAX_STRIPS})"
+			)));
+		}
+
+		let mask = if enabled {
+			self.enabled_strips | 1 << strip
+		} else {
+			self.enabled_strips & !(1 << strip)
+		};
+
+		self.initialize().await?;
+		self.send_command(SET_STRIP_ENABLE_MESSAGE, &mask.to_le_bytes())
+			.await?;
+		self.enabled_strips = mask;
+
+		Ok(())
+	}
+
+	pub(crate) async fn set_strip_length(&mut self, strip: usize, len: usize) -> Result<()> {
+		if strip >= self.config.strips {
+			return Err(Error::InvalidConfig(format!(
+				"strip {strip} out of range (0..{})",
+				self.config.strips
+			)));
+		}
+		let Ok(len) = u16::try_from(len) else {
+			return Err(Error::InvalidConfig(format!(
+				"{len} leds on strip {strip} are too many"
+			)));
+		};
+
+		let previous = self.config.strip_lengths;
+		let mut lengths = previous.unwrap_or([self.config.leds as u16; MAX_STRIPS]);
+		lengths[strip] = len;
+		self.config.strip_lengths = Some(lengths);
+
+		let result = self.ensure_configured().await;
+		if let Err(Error::InvalidConfig(_)) = result {
+			self.config.strip_lengths = previous;
+		}
+
+		result
+	}
+
+	pub(crate) async fn set_strip_color_order(
+		&mut self,
+		strip: usize,
+		order: ColorOrder,
+	) -> Result<()> {
+		if strip >= MAX_STRIPS {
+			return Err(Error::InvalidConfig(format!(
+				"strip {strip} out of range (0..{MAX_STRIPS})"
+			)));
+		}
+
+		let previous = mem::replace(&mut self.config.color_orders[strip], order);
+		let result = self.ensure_configured().await;
+		if let Err(Error::InvalidConfig(_)) = result {
+			self.config.color_orders[strip] = previous;
+		}
+
+		result
+	}
+
+	pub(crate) async fn set_reset_duration(&mut self, duration: Duration) -> Result<()> {
+		let micros = duration.as_micros();
+		if !(MIN_RESET_DURATION_US as u128..=MAX_RESET_DURATION_US as u128).contains(&micros) {
+			return Err(Error::InvalidConfig(format!(
+				"reset duration of {micros}µs out of range \
+				 ({MIN_RESET_DURATION_US}..={MAX_RESET_DURATION_US}µs)"
+			)));
+		}
+		let micros = micros as u32;
+
+		self.initialize().await?;
+		self.send_command(SET_RESET_DURATION_MESSAGE, &micros.to_le_bytes())
+			.await?;
+		self.reset_duration_us = Some(micros);
+
+		Ok(())
+	}
+
+	pub(crate) async fn set_led_timing(&mut self, timing: LedTiming) -> Result<()> {
+		self.initialize().await?;
+		self.send_command(SET_LED_TIMING_MESSAGE, &timing.id().to_le_bytes())
+			.await?;
+		self.led_timing = timing;
+
+		Ok(())
+	}
+
+	/// Makes sure the device is waiting for a command.
+	async fn initialize(&mut self) -> Result<()> {
+		if !self.initialized {
+			self.reset_to_command().await?;
+			self.initialized = true;
+		}
+
+		Ok(())
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		instrument(
+			level = "debug",
+			skip_all,
+			fields(
+				strips = self.config.strips,
+				leds = self.config.leds_total(),
+				bytes = leds.len(),
+			)
+		)
+	)]
+	pub(crate) async fn send_leds(&mut self, leds: &[u8]) -> Result<WriteResult> {
+		self.send_leds_as(leds, self.config.buffer_layout).await
+	}
+
+	pub(crate) async fn send_frame(&mut self, frame: &Frame) -> Result<WriteResult> {
+		self.send_leds_as(frame.as_bytes(), BufferLayout::StripMajor)
+			.await
+	}
+
+	async fn send_leds_as(&mut self, leds: &[u8], layout: BufferLayout) -> Result<WriteResult> {
+		let expected = self.transfer_size();
+		if leds.len() != expected {
+			return Err(Error::BufferSizeMismatch {
+				expected,
+				received: leds.len(),
+			});
+		}
+
+		if !self.configured {
+			self.configure().await?;
+		}
+
+		let checksum = self.skip_unchanged.then(|| checksum(leds));
+		if checksum.is_some() && checksum == self.last_checksum {
+			debug!("frame unchanged, skipping");
+			// nothing is sent, so there is nothing to time
+			#[cfg(feature = "timings")]
+			return Ok(WriteResult::default());
+			#[cfg(not(feature = "timings"))]
+			return Ok(());
+		}
+
+		let mut scratch = mem::take(&mut self.scratch);
+		let leds = self.encode_leds(leds, layout, &mut scratch);
+		let result = self.send_update(leds).await;
+		self.scratch = scratch;
+
+		match &result {
+			Ok(_) => self.last_checksum = checksum,
+			Err(e) if self.blank_on_error => self.blank_after_error(e).await,
+			Err(_) => {}
+		}
+		result
+	}
+
+	/// Best effort to turn off the LEDs after `error`, see [`Self::set_blank_on_error`].
+	async fn blank_after_error(&mut self, error: &Error) {
+		warn!("sending frame failed, blanking the LEDs: {error}");
+
+		let blank = vec![0; self.config.frame_bytes()];
+		// doesn't go through `send_leds` so a failure here can't blank again
+		let result = match self.reset_to_command().await {
+			Ok(()) => self.send_update(&blank).await.map(|_| ()),
+			Err(e) => Err(e),
+		};
+		if let Err(e) = result {
+			warn!("blanking the LEDs failed: {e}");
+		}
+	}
+
+	/// Sends the encoded LEDs, resending corrupted frames and retrying as configured with
+	/// [`Self::set_frame_retries`].
+	async fn send_update(&mut self, leds: &[u8]) -> Result<WriteResult> {
+		let mut encoded = mem::take(&mut self.update_data);
+		let (command, data) = update_command(
+			&self.config,
+			self.update_checksums,
+			self.rle_updates,
+			leds,
+			&mut encoded,
+		);
+
+		let mut retry = Retry::new(self.frame_retries);
+		let result = loop {
+			let error = match self.send_command(command, data).await {
+				Ok(result) => break Ok(result),
+				Err(e) => e,
+			};
+
+			match retry.on_error(&error) {
+				Some(Recovery::Resend) => self.stats.resends += 1,
+				Some(Recovery::Reset) => {
+					if let Err(e) = self.reset_to_command().await {
+						break Err(e);
+					}
+				}
+				None => break Err(error),
+			}
+		};
+
+		self.update_data = encoded;
+		result
+	}
+
+	// `WriteResult` is `()` without the timings feature
+	#[allow(clippy::let_unit_value)]
+	pub(crate) async fn send_leds_owned(
+		&mut self,
+		leds: Vec<u8>,
+	) -> Result<(WriteResult, Vec<u8>)> {
+		let result = self.send_leds(&leds).await?;
+
+		Ok((result, leds))
+	}
+
+	pub(crate) async fn send_leds_u16(&mut self, pixels: &[[u16; 3]]) -> Result<WriteResult> {
+		let leds = downscale_u16(pixels, self.dither_error.as_mut());
+		self.send_leds(&leds).await
+	}
+
+	pub(crate) async fn send_flat(&mut self, pixels: &[RGB]) -> Result<WriteResult> {
+		let leds = flat_to_strip_major(&self.config, pixels)?;
+		self.send_leds_as(&leds, BufferLayout::StripMajor).await
+	}
+
+	// `WriteResult` is `()` without the timings feature
+	#[allow(clippy::let_unit_value)]
+	pub(crate) async fn send_leds_blocking(&mut self, leds: &[u8]) -> Result<WriteResult> {
+		let result = self.send_leds(leds).await?;
+		self.wait_until_displayed().await?;
+
+		Ok(result)
+	}
+
+	pub(crate) async fn send_diff(&mut self, prev: &Frame, next: &Frame) -> Result<WriteResult> {
+		if !next
+			.strip_lengths()
+			.iter()
+			.copied()
+			.eq(self.config.strip_lens())
+		{
+			return Err(Error::BufferSizeMismatch {
+				expected: self.config.rgb_buffer_size(),
+				received: next.as_bytes().len(),
+			});
+		}
+
+		if !self.configured {
+			self.configure().await?;
+		}
+
+		// the device can't apply diffs to the packed RGBW data
+		if self.config.rgbw_mode.is_some() {
+			return self.send_frame(next).await;
+		}
+
+		let changes = match self.channel_scale {
+			NO_CHANNEL_SCALE => encode_diff(prev, next),
+			scale => encode_diff(&prev.scaled(scale), &next.scaled(scale)),
+		};
+
+		match changes {
+			Some(changes) => self.send_command(DIFF_MESSAGE, &changes).await,
+			// like any other frame, so the retries, checksums and encodings apply
+			None => self.send_frame(next).await,
+		}
+	}
+
+	/// The LEDs as the device expects them, reordered from `layout` with the channel scale and RGBW
+	/// conversion applied.
+	///
+	/// Returns `leds` without a copy if nothing has to change, otherwise everything is done in one
+	/// pass into `scratch`, which keeps its allocation between frames.
+	fn encode_leds<'a>(
+		&self,
+		leds: &'a [u8],
+		layout: BufferLayout,
+		scratch: &'a mut Vec<u8>,
+	) -> &'a [u8] {
+		if !needs_encoding(&self.config, layout, self.channel_scale) {
+			return leds;
+		}
+
+		encode_leds(&self.config, layout, self.channel_scale, leds, scratch);
+		scratch
+	}
+
+	pub(crate) async fn run_animation(
+		&mut self,
+		mut animation: impl Animation,
+		fps: u32,
+	) -> Result<()> {
+		let interval = animation::frame_interval(fps);
+		let mut frame = Frame::new(&self.config);
+		let start = Instant::now();
+		let mut next = start;
+
+		while !animation.is_finished() {
+			animation.render(&mut frame, start.elapsed());
+			self.send_frame(&frame).await?;
+
+			next += interval;
+			let now = Instant::now();
+			if next > now {
+				T::sleep(next - now).await;
+			} else {
+				next = now;
+			}
+		}
+
+		Ok(())
+	}
+
+	pub(crate) async fn send_raw(
+		&mut self,
+		command: &[u8; MESSAGE_TYPE_LEN],
+		data: &[u8],
+	) -> Result<WriteResult> {
+		self.initialize().await?;
+		self.send_command(command, data).await
+	}
+
+	pub(crate) async fn send_vendor_command(
+		&mut self,
+		id: &[u8; VENDOR_ID_LEN],
+		data: &[u8],
+	) -> Result<WriteResult> {
+		if data.len() > MAX_VENDOR_DATA_LEN {
+			return Err(Error::VendorDataTooLarge {
+				max:      MAX_VENDOR_DATA_LEN,
+				received: data.len(),
+			});
+		}
+
+		let mut message = u32::to_le_bytes(data.len() as u32).to_vec();
+		message.extend_from_slice(data);
+
+		self.initialize().await?;
+		self.send_command(&Command::Vendor(*id).message(), &message)
+			.await
+	}
+
+	pub(crate) async fn reboot_to_bootloader(&mut self) -> Result<()> {
+		if self.stream.is_some() {
+			return Err(Error::Streaming);
+		}
+		// the magic has to arrive as the data of the command, not in the middle of another one
+		self.initialize().await?;
+
+		self.send_command(REBOOT_TO_BOOTLOADER_MESSAGE, BOOTLOADER_MAGIC)
+			.await?;
+		self.initialized = false;
+		self.configured = false;
+		self.capabilities = None;
+
+		Ok(())
+	}
+
+	pub(crate) fn set_dtr(&mut self, level: bool) -> Result<()> {
+		self.port.port().write_data_terminal_ready(level)?;
+		Ok(())
+	}
+
+	pub(crate) fn set_rts(&mut self, level: bool) -> Result<()> {
+		self.port.port().write_request_to_send(level)?;
+		Ok(())
+	}
+
+	pub(crate) fn clear_to_send(&mut self) -> Result<bool> {
+		Ok(self.port.port().read_clear_to_send()?)
+	}
+
+	pub(crate) fn data_set_ready(&mut self) -> Result<bool> {
+		Ok(self.port.port().read_data_set_ready()?)
+	}
+
+	pub(crate) async fn begin_stream(&mut self, ack_interval: u32) -> Result<()> {
+		if !self.configured {
+			self.configure().await?;
+		}
+
+		self.send_command(STREAM_MESSAGE, &u32::to_le_bytes(ack_interval))
+			.await?;
+		self.stream = Some(Stream::new(ack_interval));
+
+		Ok(())
+	}
+
+	pub(crate) async fn send_frame_streamed(&mut self, leds: &[u8]) -> Result<()> {
+		let expected = self.transfer_size();
+		let Some(stream) = &mut self.stream else {
+			return Err(Error::NotStreaming);
+		};
+
+		if leds.len() != expected {
+			return Err(Error::BufferSizeMismatch {
+				expected,
+				received: leds.len(),
+			});
+		}
+
+		let ack_due = stream.frame_sent();
+		let mut scratch = mem::take(&mut self.scratch);
+		let leds = self.encode_leds(leds, self.config.buffer_layout, &mut scratch);
+		let result = self.write_stream_frame(leds, ack_due).await;
+		self.scratch = scratch;
+		result?;
+		self.stats.frames_sent += 1;
+
+		Ok(())
+	}
+
+	async fn write_stream_frame(&mut self, leds: &[u8], ack_due: bool) -> Result<()> {
+		if self.serial_write(STREAM_FRAME_MESSAGE).await? != STREAM_FRAME_MESSAGE.len() {
+			return Err(Error::IncompleteWrite);
+		}
+		if self.serial_write(leds).await? != leds.len() {
+			return Err(Error::IncompleteWrite);
+		}
+		if ack_due {
+			self.read_response(DEVICE_OK_MESSAGE).await?;
+		}
+
+		Ok(())
+	}
+
+	pub(crate) async fn end_stream(&mut self) -> Result<()> {
+		if self.stream.take().is_none() {
+			return Err(Error::NotStreaming);
+		}
+
+		if self.serial_write(STREAM_END_MESSAGE).await? != STREAM_END_MESSAGE.len() {
+			return Err(Error::IncompleteWrite);
+		}
+
+		self.read_response(DEVICE_OK_MESSAGE).await
+	}
+
+	/// Sends a command and its data, then waits for the device to acknowledge it.
+	///
+	/// The data can be as large as a full frame, the device puts the USB packets back together and
+	/// only acknowledges once all of it arrived.
+	#[cfg_attr(
+		feature = "tracing",
+		instrument(
+			level = "trace",
+			skip_all,
+			fields(
+				command = protocol::command_name(command),
+				bytes = data.len(),
+				command_us = tracing::field::Empty,
+				data_us = tracing::field::Empty,
+			)
+		)
+	)]
+	// `WriteResult` is `()` without the timings feature
+	#[allow(clippy::let_unit_value)]
+	async fn send_command(
+		&mut self,
+		command: &[u8; MESSAGE_TYPE_LEN],
+		data: &[u8],
+	) -> Result<WriteResult> {
+		if self.stream.is_some() {
+			return Err(Error::Streaming);
+		}
+		// the command could change what the LEDs show
+		self.last_checksum = None;
+
+		let mut exchange = Exchange::command(command, data, self.framed, (self.clock)());
+		let result = self.exchange(&mut exchange).await?;
+		if is_frame_command(command) {
+			self.stats.frames_sent += 1;
+		}
+
+		Ok(result)
+	}
+
+	/// Sends a query and reads the response, which has to fill the `response` buffer.
+	async fn query(&mut self, command: &[u8; MESSAGE_TYPE_LEN], response: &mut [u8]) -> Result<()> {
+		if self.stream.is_some() {
+			return Err(Error::Streaming);
+		}
+
+		let mut exchange = Exchange::query(command, self.framed, (self.clock)());
+		self.exchange(&mut exchange).await?;
+
+		self.port.read_exact(response, protocol::TIMEOUT).await?;
+
+		Ok(())
+	}
+
+	/// Does the writes and reads `exchange` asks for until the device acknowledged the command.
+	async fn exchange(&mut self, exchange: &mut Exchange<'_>) -> Result<WriteResult> {
+		loop {
+			match exchange.next((self.clock)()) {
+				Step::Write(bytes) => {
+					if self.serial_write(bytes).await? != bytes.len() {
+						return Err(Error::IncompleteWrite);
+					}
+				}
+				Step::Read => {
+					let response = self.read_device_message().await?;
+					exchange.on_response(&response)?;
+				}
+				Step::Done(result) => return Ok(result),
+			}
+		}
+	}
+
+	async fn read_response(&mut self, expected: &[u8; DEVICE_MESSAGE_TYPE_LEN]) -> Result<()> {
+		let output = self.read_device_message().await?;
+		check_response(&output, expected)
+	}
+
+	/// Reads the response of the device, reads that return nothing are retried until
+	/// [`protocol::TIMEOUT`] passed, unless the port was closed, see [`ResponseRead`].
+	pub(crate) async fn read_device_message(&mut self) -> Result<[u8; DEVICE_MESSAGE_TYPE_LEN]> {
+		let mut output = [0u8; DEVICE_MESSAGE_TYPE_LEN];
+		let deadline = Instant::now() + protocol::TIMEOUT;
+		let mut read = ResponseRead::default();
+
+		loop {
+			let remaining = deadline.saturating_duration_since(Instant::now());
+			let result = self.port.read(&mut output, remaining).await;
+			if read.on_read(result)? {
+				trace!("received {}", protocol::describe_response(&output));
+				return Ok(output);
+			}
+
+			if Instant::now() >= deadline {
+				return Err(Error::NoResponse);
+			}
+		}
+	}
+
+	async fn serial_write(&mut self, buffer: &[u8]) -> Result<usize> {
+		match self.port.write_all(buffer).await {
+			Ok(_) => {
+				self.stats.bytes_sent += buffer.len() as u64;
+				Ok(buffer.len())
+			}
+			// Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+			// 	println!("WARNING: serial timeout");
+			// 	Ok(0)
+			// }
+			// Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+			// 	println!("WARNING: serial interrupted");
+			// 	Ok(0)
+			// }
+			Err(e) => {
+				self.stats.write_errors += 1;
+				Err(e.into())
+			}
+		}
+	}
+}
@@ -0,0 +1,58 @@
+use std::future::Future;
+
+use crate::{Config, Result, WriteResult};
+
+/// The operations every blocking backend supports, to write code once for the device and the
+/// simulator.
+pub trait Ws2812Controller {
+	/// The current configuration of the instance.
+	fn config(&self) -> &Config;
+
+	/// Sets the configuration and sends it to the device.
+	fn set_config(&mut self, config: Config) -> Result<()>;
+
+	/// Sends the configuration to the device.
+	fn configure(&mut self) -> Result<()>;
+
+	/// The amount of bytes [`Self::send_leds`] expects for the current config.
+	fn transfer_size(&self) -> usize {
+		self.config().buffer_size()
+	}
+
+	/// Sends a full frame, the length must be [`Self::transfer_size`].
+	fn send_leds(&mut self, leds: &[u8]) -> Result<WriteResult>;
+
+	/// Turns off every LED.
+	fn off(&mut self) -> Result<WriteResult> {
+		let leds = vec![0; self.transfer_size()];
+		self.send_leds(&leds)
+	}
+}
+
+/// Like [`Ws2812Controller`], for the async backends.
+pub trait AsyncWs2812Controller: Send {
+	/// The current configuration of the instance.
+	fn config(&self) -> &Config;
+
+	/// Sets the configuration and sends it to the device.
+	fn set_config(&mut self, config: Config) -> impl Future<Output = Result<()>> + Send;
+
+	/// Sends the configuration to the device.
+	fn configure(&mut self) -> impl Future<Output = Result<()>> + Send;
+
+	/// The amount of bytes [`Self::send_leds`] expects for the current config.
+	fn transfer_size(&self) -> usize {
+		self.config().buffer_size()
+	}
+
+	/// Sends a full frame, the length must be [`Self::transfer_size`].
+	fn send_leds(&mut self, leds: &[u8]) -> impl Future<Output = Result<WriteResult>> + Send;
+
+	/// Turns off every LED.
+	fn off(&mut self) -> impl Future<Output = Result<WriteResult>> + Send {
+		async move {
+			let leds = vec![0; self.transfer_size()];
+			self.send_leds(&leds).await
+		}
+	}
+}
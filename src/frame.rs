@@ -0,0 +1,414 @@
+use thiserror::Error;
+
+use crate::{
+	color::{scale8, scale_channels},
+	Config,
+	Layout,
+	BYTES_PER_LED,
+	HSV,
+	RGB,
+};
+
+/// The LED data for one update in the layout the device expects.
+///
+/// The data is strip-major, all LEDs of the first strip followed by all LEDs of the second strip and
+/// so on, with 3 bytes (red, green, blue) per LED. With [`Config::strip_lengths`] every strip only
+/// takes up as many LEDs as it is long.
+///
+/// Frames are equal if they have the same strip lengths and LED data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+	strip_lengths: Vec<usize>,
+	data:          Vec<u8>,
+}
+
+impl Frame {
+	/// Create a new frame with all LEDs turned off.
+	pub fn new(config: &Config) -> Self {
+		Self {
+			strip_lengths: config.strip_lens().collect(),
+			data:          vec![0; config.rgb_buffer_size()],
+		}
+	}
+
+	/// Create a new frame with the color of every LED returned by `f`, which is called with the
+	/// strip and the index on the strip in the order the LEDs are sent.
+	pub fn from_fn(config: &Config, mut f: impl FnMut(usize, usize) -> RGB) -> Self {
+		let mut frame = Self::new(config);
+		for (strip, led, pixel) in frame.pixels_mut() {
+			*pixel = f(strip, led).into();
+		}
+		frame
+	}
+
+	pub fn strips(&self) -> usize {
+		self.strip_lengths.len()
+	}
+
+	/// The amount of LEDs on the longest strip.
+	pub fn leds(&self) -> usize {
+		self.strip_lengths.iter().copied().max().unwrap_or(0)
+	}
+
+	/// The amount of LEDs on every strip.
+	pub fn strip_lengths(&self) -> &[usize] {
+		&self.strip_lengths
+	}
+
+	/// The raw bytes that get sent to the device.
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.data
+	}
+
+	pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+		&mut self.data
+	}
+
+	/// A hash of the LED data to cheaply check whether two frames are the same.
+	///
+	/// Not cryptographic, and only stable within the same version of this crate.
+	pub fn checksum(&self) -> u64 {
+		checksum(&self.data)
+	}
+
+	/// Returns the color of a single LED.
+	///
+	/// Panics if the strip or LED is out of range.
+	pub fn pixel(&self, strip: usize, led: usize) -> [u8; BYTES_PER_LED] {
+		let idx = self.byte_index(strip, led);
+
+		let mut color = [0; BYTES_PER_LED];
+		color.copy_from_slice(&self.data[idx..idx + BYTES_PER_LED]);
+		color
+	}
+
+	/// Sets the color of a single LED.
+	///
+	/// Panics if the strip or LED is out of range.
+	pub fn set_pixel(&mut self, strip: usize, led: usize, color: impl Into<[u8; BYTES_PER_LED]>) {
+		let idx = self.byte_index(strip, led);
+
+		self.data[idx..idx + BYTES_PER_LED].copy_from_slice(&color.into());
+	}
+
+	/// Sets the color of a single LED from HSV, converted with [`HSV::to_rgb`].
+	///
+	/// Panics if the strip or LED is out of range.
+	pub fn set_pixel_hsv(&mut self, strip: usize, led: usize, hsv: HSV) {
+		self.set_pixel(strip, led, hsv.to_rgb());
+	}
+
+	/// Adds the color to a single LED instead of replacing it, see [`RGB::saturating_add`].
+	///
+	/// Panics if the strip or LED is out of range.
+	pub fn add_pixel(&mut self, strip: usize, led: usize, color: impl Into<[u8; BYTES_PER_LED]>) {
+		let current = RGB::from(self.pixel(strip, led));
+
+		self.set_pixel(strip, led, current.saturating_add(color.into().into()));
+	}
+
+	/// Like [`Self::set_pixel`], but returns an error instead of panicking if the strip or LED is
+	/// out of range.
+	pub fn try_set_pixel(
+		&mut self,
+		strip: usize,
+		led: usize,
+		color: impl Into<[u8; BYTES_PER_LED]>,
+	) -> Result<(), OutOfBounds> {
+		let idx = self.checked_byte_index(strip, led)?;
+
+		self.data[idx..idx + BYTES_PER_LED].copy_from_slice(&color.into());
+		Ok(())
+	}
+
+	/// Iterates over the LEDs that differ from `prev`, with their strip, index on the strip and
+	/// color in this frame.
+	///
+	/// Nothing is returned if the frames have different strip lengths, as the LEDs can't be matched
+	/// up.
+	pub fn changed_pixels<'a>(
+		&'a self,
+		prev: &'a Frame,
+	) -> impl Iterator<Item = (usize, usize, RGB)> + 'a {
+		let len = match self.strip_lengths == prev.strip_lengths {
+			true => self.data.len(),
+			false => 0,
+		};
+
+		positions(&self.strip_lengths)
+			.zip(self.data[..len].chunks_exact(BYTES_PER_LED))
+			.zip(prev.data.chunks_exact(BYTES_PER_LED))
+			.filter(|((_, next), prev)| next != prev)
+			.map(|(((strip, led), next), _)| (strip, led, RGB::new(next[0], next[1], next[2])))
+	}
+
+	/// Iterates over all LEDs with their strip and index on the strip.
+	pub fn pixels_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut [u8; BYTES_PER_LED])> {
+		positions(&self.strip_lengths)
+			.zip(self.data.chunks_exact_mut(BYTES_PER_LED))
+			.map(|((strip, led), color)| (strip, led, color.try_into().unwrap()))
+	}
+
+	/// Iterates over the rows of a matrix panel from top to bottom, every row has the LEDs from
+	/// left to right.
+	///
+	/// Panics if the layout has more LEDs than the frame.
+	pub fn rows(&mut self, layout: Layout) -> impl Iterator<Item = Vec<&mut [u8; BYTES_PER_LED]>> {
+		let mut pixels = self.layout_pixels(layout);
+
+		(0..layout.height).map(move |y| {
+			(0..layout.width)
+				.map(|x| take_pixel(&mut pixels, layout.index(x, y)))
+				.collect()
+		})
+	}
+
+	/// Iterates over the columns of a matrix panel from left to right, every column has the LEDs
+	/// from top to bottom.
+	///
+	/// Panics if the layout has more LEDs than the frame.
+	pub fn columns(
+		&mut self,
+		layout: Layout,
+	) -> impl Iterator<Item = Vec<&mut [u8; BYTES_PER_LED]>> {
+		let mut pixels = self.layout_pixels(layout);
+
+		(0..layout.width).map(move |x| {
+			(0..layout.height)
+				.map(|y| take_pixel(&mut pixels, layout.index(x, y)))
+				.collect()
+		})
+	}
+
+	/// Rotates the LEDs of every strip towards the end by `n`, the last LEDs wrap around to the start.
+	pub fn rotate_right(&mut self, n: usize) {
+		for strip in 0..self.strips() {
+			let bytes = self.strip_bytes_mut(strip);
+			let len = bytes.len() / BYTES_PER_LED;
+			if len > 0 {
+				bytes.rotate_right(n % len * BYTES_PER_LED);
+			}
+		}
+	}
+
+	/// Rotates the LEDs of every strip towards the start by `n`, the first LEDs wrap around to the end.
+	pub fn rotate_left(&mut self, n: usize) {
+		for strip in 0..self.strips() {
+			let bytes = self.strip_bytes_mut(strip);
+			let len = bytes.len() / BYTES_PER_LED;
+			if len > 0 {
+				bytes.rotate_left(n % len * BYTES_PER_LED);
+			}
+		}
+	}
+
+	/// Shifts the LEDs of every strip towards the end by `n`, the LEDs at the start are set to `fill`.
+	pub fn shift_right(&mut self, n: usize, fill: impl Into<[u8; BYTES_PER_LED]>) {
+		let fill = fill.into();
+		for strip in 0..self.strips() {
+			let bytes = self.strip_bytes_mut(strip);
+			let n = n.min(bytes.len() / BYTES_PER_LED) * BYTES_PER_LED;
+
+			bytes.rotate_right(n);
+			for color in bytes[..n].chunks_exact_mut(BYTES_PER_LED) {
+				color.copy_from_slice(&fill);
+			}
+		}
+	}
+
+	/// Shifts the LEDs of every strip towards the start by `n`, the LEDs at the end are set to `fill`.
+	pub fn shift_left(&mut self, n: usize, fill: impl Into<[u8; BYTES_PER_LED]>) {
+		let fill = fill.into();
+		for strip in 0..self.strips() {
+			let bytes = self.strip_bytes_mut(strip);
+			let n = n.min(bytes.len() / BYTES_PER_LED) * BYTES_PER_LED;
+			let end = bytes.len() - n;
+
+			bytes.rotate_left(n);
+			for color in bytes[end..].chunks_exact_mut(BYTES_PER_LED) {
+				color.copy_from_slice(&fill);
+			}
+		}
+	}
+
+	/// Reverses the order of the LEDs on a strip.
+	///
+	/// Panics if the strip is out of range.
+	pub fn reverse_strip(&mut self, strip: usize) {
+		assert!(
+			strip < self.strips(),
+			"strip {strip} out of range (0..{})",
+			self.strips()
+		);
+
+		// reversing all bytes also reverses the channels, which are flipped back per LED
+		let bytes = self.strip_bytes_mut(strip);
+		bytes.reverse();
+		for color in bytes.chunks_exact_mut(BYTES_PER_LED) {
+			color.reverse();
+		}
+	}
+
+	/// Fills every strip with a linear gradient from `from` on the first LED to `to` on the last.
+	///
+	/// Every strip shows the whole gradient, regardless of its length.
+	pub fn fill_gradient(&mut self, from: RGB, to: RGB) {
+		self.fill_along_strips(|step, steps| {
+			RGB::new(
+				lerp(from.r, to.r, step, steps),
+				lerp(from.g, to.g, step, steps),
+				lerp(from.b, to.b, step, steps),
+			)
+		});
+	}
+
+	/// Like [`Self::fill_gradient`], but interpolates in HSV, the hue takes the shorter way around
+	/// the color wheel.
+	pub fn fill_gradient_hsv(&mut self, from: HSV, to: HSV) {
+		let hue_diff = to.hue.wrapping_sub(from.hue) as i8 as i32;
+
+		self.fill_along_strips(|step, steps| {
+			let hue = from
+				.hue
+				.wrapping_add((hue_diff * step as i32 / steps as i32) as u8);
+
+			HSV::new(
+				hue,
+				lerp(from.saturation, to.saturation, step, steps),
+				lerp(from.value, to.value, step, steps),
+			)
+			.to_rgb()
+		});
+	}
+
+	/// Blends `other` over this frame, `alpha` 0 keeps this frame and 255 replaces it with `other`.
+	///
+	/// Panics if the frames don't have the same strip lengths.
+	pub fn blend(&mut self, other: &Frame, alpha: u8) {
+		assert_eq!(
+			self.strip_lengths, other.strip_lengths,
+			"frames with different strip lengths can't be blended"
+		);
+
+		for (channel, &over) in self.data.iter_mut().zip(&other.data) {
+			*channel = scale8(*channel, 255 - alpha) + scale8(over, alpha);
+		}
+	}
+
+	/// Dims every channel of the frame to `brightness` out of 255, for example for a background layer
+	/// before blending, unlike [`crate::SerialWs2812::set_channel_scale`] which applies to every
+	/// frame that is sent.
+	pub fn scale(&mut self, brightness: u8) {
+		for channel in &mut self.data {
+			*channel = scale8(*channel, brightness);
+		}
+	}
+
+	/// A copy of the frame with every channel scaled, see [`scale_channels`].
+	pub(crate) fn scaled(&self, scale: [u8; 3]) -> Frame {
+		let mut frame = self.clone();
+		scale_channels(&mut frame.data, BYTES_PER_LED, scale);
+		frame
+	}
+
+	/// Sets the LEDs of every strip to `gradient(led, last_led)`.
+	fn fill_along_strips(&mut self, gradient: impl Fn(usize, usize) -> RGB) {
+		for strip in 0..self.strips() {
+			let steps = self.strip_lengths[strip].saturating_sub(1).max(1);
+
+			for (led, bytes) in self
+				.strip_bytes_mut(strip)
+				.chunks_exact_mut(BYTES_PER_LED)
+				.enumerate()
+			{
+				let color: [u8; BYTES_PER_LED] = gradient(led, steps).into();
+				bytes.copy_from_slice(&color);
+			}
+		}
+	}
+
+	/// The LEDs of the matrix, every LED can be taken out once.
+	fn layout_pixels(&mut self, layout: Layout) -> Vec<Option<&mut [u8; BYTES_PER_LED]>> {
+		let leds = self.data.len() / BYTES_PER_LED;
+		assert!(
+			layout.len() <= leds,
+			"layout with {} leds doesn't fit into {leds} leds",
+			layout.len()
+		);
+
+		self.data
+			.chunks_exact_mut(BYTES_PER_LED)
+			.take(layout.len())
+			.map(|color| Some(color.try_into().unwrap()))
+			.collect()
+	}
+
+	/// The bytes of a single strip.
+	fn strip_bytes_mut(&mut self, strip: usize) -> &mut [u8] {
+		let start = self.strip_lengths[..strip].iter().sum::<usize>() * BYTES_PER_LED;
+		let end = start + self.strip_lengths[strip] * BYTES_PER_LED;
+
+		&mut self.data[start..end]
+	}
+
+	fn byte_index(&self, strip: usize, led: usize) -> usize {
+		self.checked_byte_index(strip, led)
+			.unwrap_or_else(|e| panic!("{e}"))
+	}
+
+	fn checked_byte_index(&self, strip: usize, led: usize) -> Result<usize, OutOfBounds> {
+		let strip_len = self.strip_lengths.get(strip).copied().unwrap_or(0);
+		if led >= strip_len {
+			return Err(OutOfBounds {
+				strip,
+				led,
+				strips: self.strips(),
+				strip_len,
+			});
+		}
+
+		let start = self.strip_lengths[..strip].iter().sum::<usize>();
+		Ok((start + led) * BYTES_PER_LED)
+	}
+}
+
+/// A strip or LED outside of the frame was accessed.
+#[derive(Error, Clone, Copy, Debug, PartialEq, Eq)]
+#[error("strip {strip} led {led} out of range ({strips} strips, {strip_len} leds on the strip)")]
+pub struct OutOfBounds {
+	pub strip:     usize,
+	pub led:       usize,
+	/// The amount of strips in the frame.
+	pub strips:    usize,
+	/// The amount of LEDs on the strip, 0 if the strip is out of range.
+	pub strip_len: usize,
+}
+
+/// The strip and index on the strip of every LED, in the order of the frame.
+fn positions(strip_lengths: &[usize]) -> impl Iterator<Item = (usize, usize)> + '_ {
+	strip_lengths
+		.iter()
+		.enumerate()
+		.flat_map(|(strip, &len)| (0..len).map(move |led| (strip, led)))
+}
+
+/// FNV-1a over `bytes`, see [`Frame::checksum`].
+pub(crate) fn checksum(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+		(hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+	})
+}
+
+/// Interpolates between `from` and `to`, `step` out of `steps`.
+fn lerp(from: u8, to: u8, step: usize, steps: usize) -> u8 {
+	(from as i32 + (to as i32 - from as i32) * step as i32 / steps as i32) as u8
+}
+
+fn take_pixel<'a>(
+	pixels: &mut [Option<&'a mut [u8; BYTES_PER_LED]>],
+	idx: usize,
+) -> &'a mut [u8; BYTES_PER_LED] {
+	pixels[idx]
+		.take()
+		.expect("every led is only part of one row and column")
+}
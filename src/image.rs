@@ -0,0 +1,114 @@
+//! Sending images from the `image` crate to matrix panels.
+
+use ::image::RgbImage;
+
+use crate::{
+	Config,
+	Error,
+	Frame,
+	Layout,
+	Result,
+	RgbwConversion,
+	SerialWs2812,
+	WriteResult,
+	BYTES_PER_LED,
+};
+
+/// Maps the pixels of the image onto a frame, LEDs that aren't part of the matrix stay off.
+fn encode_image(image: &RgbImage, layout: Layout, config: &Config) -> Result<Frame> {
+	// frames are RGB, there is nothing to take the white channel from
+	if config.rgbw_mode == Some(RgbwConversion::None) {
+		return Err(Error::InvalidConfig(
+			"images can't be sent to RGBW strips without a conversion".to_string(),
+		));
+	}
+
+	let (width, height) = image.dimensions();
+	if (width as usize, height as usize) != (layout.width, layout.height) {
+		return Err(Error::ImageSizeMismatch {
+			expected: (layout.width, layout.height),
+			received: (width as usize, height as usize),
+		});
+	}
+
+	let mut frame = Frame::new(config);
+	let leds = frame.as_bytes_mut();
+	if layout.len() * BYTES_PER_LED > leds.len() {
+		return Err(Error::BufferSizeMismatch {
+			expected: leds.len(),
+			received: layout.len() * BYTES_PER_LED,
+		});
+	}
+
+	for (x, y, pixel) in image.enumerate_pixels() {
+		let idx = layout.index(x as usize, y as usize) * BYTES_PER_LED;
+		leds[idx..idx + BYTES_PER_LED].copy_from_slice(&pixel.0);
+	}
+
+	Ok(frame)
+}
+
+impl SerialWs2812 {
+	/// Sends the image to a matrix panel wired according to `layout`.
+	///
+	/// The image is neither resized nor cropped, its dimensions have to match the layout exactly and
+	/// the layout has to fit into the configured LEDs. [`Config::buffer_layout`] doesn't apply, the
+	/// layout decides where the pixels go. RGBW strips need a conversion, with
+	/// [`RgbwConversion::None`] this returns [`Error::InvalidConfig`].
+	pub fn send_image(&mut self, image: &RgbImage, layout: Layout) -> Result<WriteResult> {
+		let frame = encode_image(image, layout, self.config())?;
+		self.send_frame(&frame)
+	}
+}
+
+#[cfg(feature = "tokio")]
+impl crate::tokio::SerialWs2812 {
+	/// Sends the image to a matrix panel wired according to `layout`.
+	///
+	/// The image is neither resized nor cropped, its dimensions have to match the layout exactly and
+	/// the layout has to fit into the configured LEDs. [`Config::buffer_layout`] doesn't apply, the
+	/// layout decides where the pixels go. RGBW strips need a conversion, with
+	/// [`RgbwConversion::None`] this returns [`Error::InvalidConfig`].
+	pub async fn send_image(&mut self, image: &RgbImage, layout: Layout) -> Result<WriteResult> {
+		let frame = encode_image(image, layout, self.config())?;
+		self.send_frame(&frame).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use ::image::Rgb;
+
+	use super::*;
+	use crate::{test_port::FakePort, BufferLayout};
+
+	/// A 2x2 image where every pixel has its own color.
+	fn image() -> RgbImage {
+		RgbImage::from_fn(2, 2, |x, y| Rgb([x as u8, y as u8, 10]))
+	}
+
+	#[test]
+	fn led_major_config_keeps_the_image_in_place() {
+		let port = FakePort::new();
+		let config = Config::strips(2, 2).with_buffer_layout(BufferLayout::LedMajor);
+		let mut controller = SerialWs2812::from_port(Box::new(port.clone()), config);
+
+		controller.send_image(&image(), Layout::new(2, 2)).unwrap();
+
+		// one row per strip
+		let expected = [0, 0, 10, 1, 0, 10, 0, 1, 10, 1, 1, 10];
+		assert!(port.written().ends_with(&expected));
+	}
+
+	#[test]
+	fn rgbw_without_conversion_is_rejected() {
+		let port = FakePort::new();
+		let config = Config::strips(2, 2).with_rgbw_mode(RgbwConversion::None);
+		let mut controller = SerialWs2812::from_port(Box::new(port.clone()), config);
+
+		let result = controller.send_image(&image(), Layout::new(2, 2));
+
+		assert!(matches!(result, Err(Error::InvalidConfig(_))));
+		assert!(port.written().is_empty());
+	}
+}
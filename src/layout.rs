@@ -0,0 +1,53 @@
+/// How the LEDs of a matrix panel are wired.
+///
+/// The LEDs are numbered row by row from the top left, this number is the index of the LED in the
+/// strip-major frame. A panel can span multiple strips, the next strip simply continues where the
+/// last one ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+	pub width:      usize,
+	pub height:     usize,
+	/// Every other row runs backwards, like most panels are wired to keep the connections short.
+	pub serpentine: bool,
+}
+
+impl Layout {
+	pub fn new(width: usize, height: usize) -> Self {
+		Self {
+			width,
+			height,
+			serpentine: false,
+		}
+	}
+
+	pub fn serpentine(width: usize, height: usize) -> Self {
+		Self {
+			width,
+			height,
+			serpentine: true,
+		}
+	}
+
+	/// The amount of LEDs in the matrix.
+	pub fn len(&self) -> usize {
+		self.width * self.height
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// The index of the LED at `x`, `y` in the frame.
+	///
+	/// Panics if the position is outside the matrix.
+	pub fn index(&self, x: usize, y: usize) -> usize {
+		assert!(x < self.width, "x {x} out of range (0..{})", self.width);
+		assert!(y < self.height, "y {y} out of range (0..{})", self.height);
+
+		if self.serpentine && y % 2 == 1 {
+			y * self.width + self.width - 1 - x
+		} else {
+			y * self.width + x
+		}
+	}
+}
@@ -1,45 +1,154 @@
+mod animation;
+mod color;
+mod config;
+mod connection;
+mod controller;
+mod frame;
+#[cfg(feature = "image")]
+mod image;
+mod layout;
+mod log;
+mod protocol;
+mod rgbw;
+#[cfg(feature = "sacn")]
+pub mod sacn;
+mod self_test;
+#[cfg(feature = "simulator")]
+pub mod simulator;
+mod stats;
+mod telemetry;
+#[cfg(test)]
+mod test_port;
+mod text;
 #[cfg(feature = "tokio")]
 pub mod tokio;
+#[cfg(feature = "udp-realtime")]
+pub mod udp_realtime;
 
-#[cfg(feature = "timings")]
-use std::time::Instant;
 use std::{
 	io,
 	io::{Read, Write},
+	thread,
 	time::Duration,
 };
 
-pub use serial_ws2812_shared::{BYTES_PER_LED, MAX_BUFFER_SIZE, MAX_LEDS_PER_STRIP, MAX_STRIPS};
-use serial_ws2812_shared::{
-	DEVICE_ERROR_MESSAGE,
-	DEVICE_INIT_MESSAGE,
-	DEVICE_MESSAGE_TYPE_LEN,
-	DEVICE_OK_MESSAGE,
-	DEVICE_PARTIAL_MESSAGE,
-	DEVICE_PRODUCT_ID,
-	DEVICE_VENDOR_ID,
-	SET_LEDS_MESSAGE,
-	SET_STRIPS_MESSAGE,
-	UPDATE_MESSAGE,
+pub use animation::Animation;
+pub use color::{ParseColorError, HSV, RGB};
+pub use config::{BufferLayout, Capabilities, ColorOrder, Config, LedTiming};
+pub use controller::{AsyncWs2812Controller, Ws2812Controller};
+pub use frame::{Frame, OutOfBounds};
+pub use layout::Layout;
+pub use protocol::{is_device, is_device_present, list_devices, list_devices_detailed, DeviceInfo};
+pub use rgbw::{RgbwConversion, BYTES_PER_RGBW_LED};
+pub use self_test::SelfTestReport;
+pub use serial_ws2812_shared::{
+	BYTES_PER_LED,
+	DEVICE_ID_LEN,
+	FEATURE_COLOR_ORDER,
+	FEATURE_DEVICE_ID,
+	FEATURE_DIFF,
+	FEATURE_GET_CONFIG,
+	FEATURE_LED_TIMING,
+	FEATURE_LENGTH_PREFIX,
+	FEATURE_PING,
+	FEATURE_REBOOT_TO_BOOTLOADER,
+	FEATURE_RESET_DURATION,
+	FEATURE_RESYNC,
+	FEATURE_REVERSED,
+	FEATURE_RLE_UPDATE,
+	FEATURE_STREAM,
+	FEATURE_STRIP_ENABLE,
+	FEATURE_STRIP_LENGTHS,
+	FEATURE_TELEMETRY,
+	FEATURE_UPDATE_CHECKSUM,
+	FEATURE_WAIT_DISPLAYED,
+	MAX_BUFFER_SIZE,
+	MAX_LEDS_PER_STRIP,
+	MAX_RESET_DURATION_US,
+	MAX_STRIPS,
+	MAX_VENDOR_DATA_LEN,
+	MESSAGE_TYPE_LEN,
+	MIN_RESET_DURATION_US,
+	PIXEL_FORMAT_RGB,
+	VENDOR_COMMAND_PREFIX,
+	VENDOR_ID_LEN,
 };
-use serialport::{SerialPort, SerialPortType};
+use serialport::SerialPort;
+pub use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+pub use stats::{Clock, ControllerStats, ThroughputReport, Timings};
+pub use telemetry::Telemetry;
+pub use text::{ScrollText, TextRenderer, TextWriter, GLYPH_HEIGHT, GLYPH_WIDTH};
 use thiserror::Error;
-use tracing::info;
 
+use crate::{
+	connection::{block_on, Connection, Transport},
+	protocol::find_device_where,
+};
+
+/// Errors returned by the controller.
+///
+/// New variants can be added without a breaking change, the serial port and I/O errors are
+/// available through [`std::error::Error::source`].
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
 	#[error("serial to ws2812 device was not found")]
 	DeviceNotFound,
 
-	#[error("unexpected response {received:?}, expected {expected:?}")]
+	#[error("unexpected response {received}, expected {expected}")]
 	UnexpectedResponse { expected: String, received: String },
 
 	#[error("received no response from the device")]
 	NoResponse,
 
+	#[error("the device received a corrupted frame")]
+	ChecksumMismatch,
+
+	#[error(
+		"no response from the device on {0} while resetting it, the port might belong to a \
+		 different device"
+	)]
+	ResetFailed(String),
+
 	#[error("unable to send full message to device")]
 	IncompleteWrite,
 
+	#[error("buffer has {received} bytes, but the config requires {expected}")]
+	BufferSizeMismatch { expected: usize, received: usize },
+
+	#[error("vendor command has {received} bytes of data, at most {max} are supported")]
+	VendorDataTooLarge { max: usize, received: usize },
+
+	#[error("image is {received:?}, but the layout is {expected:?}")]
+	ImageSizeMismatch {
+		expected: (usize, usize),
+		received: (usize, usize),
+	},
+
+	#[error("streaming mode is active, end it before sending other commands")]
+	Streaming,
+
+	#[error("streaming mode is not active")]
+	NotStreaming,
+
+	#[error("the background sender stopped, shut it down to get the error")]
+	SenderStopped,
+
+	#[error(transparent)]
+	OutOfBounds(#[from] OutOfBounds),
+
+	#[error("invalid config: {0}")]
+	InvalidConfig(String),
+
+	#[error("the device does not support this command, the firmware might be outdated")]
+	UnsupportedCommand,
+
+	#[error("no baud rates to probe")]
+	NoBaudRates,
+
+	#[error("serial port {0} is unavailable, it might be in use by another program")]
+	PortUnavailable(String),
+
 	#[error("serial port error: {0}")]
 	SerialPort(#[from] serialport::Error),
 
@@ -49,197 +158,678 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub struct Config {
-	pub strips: usize,
-	pub leds:   usize,
-}
-
 pub struct SerialWs2812 {
-	config: Config,
-	port:   Box<dyn SerialPort>,
-
-	initialized: bool,
+	connection: Connection<Box<dyn SerialPort>>,
 }
 
 #[cfg(not(feature = "timings"))]
 pub type WriteResult = ();
 
 #[cfg(feature = "timings")]
-pub type WriteResult = (Duration, Duration);
+pub type WriteResult = Timings;
 
 impl SerialWs2812 {
 	/// Create a new instance with the given serial device and config.
+	///
+	/// The port is opened for exclusive access, so another program (or a second controller) can't
+	/// open it and mix its commands with ours, it gets [`Error::PortUnavailable`] instead. On Linux
+	/// and macOS this uses `TIOCEXCL` and `flock`, which root and programs that ignore `flock` can
+	/// get around. Windows always opens serial ports exclusively.
 	pub fn new(serial_device: String, config: Config) -> Result<Self> {
-		let baud_rate = 921_600;
+		Self::open(serial_device, config, protocol::BAUD_RATE, true)
+	}
+
+	/// Like [`Self::new`], but lets other programs open the port at the same time.
+	///
+	/// Makes no difference on Windows, where serial ports can only be opened once.
+	pub fn new_shared(serial_device: String, config: Config) -> Result<Self> {
+		Self::open(serial_device, config, protocol::BAUD_RATE, false)
+	}
 
-		let builder = serialport::new(serial_device, baud_rate).timeout(Duration::from_millis(50));
-		let port = builder.open()?;
+	/// Like [`Self::new`], but uses the config the device currently has, see
+	/// [`Self::load_config_from_device`].
+	pub fn new_using_device_config(serial_device: String) -> Result<Self> {
+		let mut controller = Self::new(serial_device, Config::default())?;
+		controller.load_config_from_device()?;
 
-		Ok(Self {
+		Ok(controller)
+	}
+
+	/// Opens the device with every baud rate in `candidates` in turn and returns the controller for
+	/// the first one the device answers a reset and a capabilities query on.
+	///
+	/// USB devices usually ignore the baud rate, this helps with adapters in between that don't.
+	/// Every rate gets about half a second to answer, the rate is kept for reconnects. Returns the
+	/// error of the last rate if none of them work, or [`Error::NoBaudRates`] if `candidates` is
+	/// empty.
+	pub fn probe_baud_rates(
+		serial_device: &str,
+		candidates: &[u32],
+		config: Config,
+	) -> Result<Self> {
+		let connection = block_on(Connection::probe_baud_rates(
+			serial_device,
+			candidates,
 			config,
-			port,
+		))?;
 
-			initialized: false,
+		Ok(Self { connection })
+	}
+
+	/// The baud rate the port was opened with.
+	pub fn baud_rate(&self) -> u32 {
+		self.connection.baud_rate()
+	}
+
+	fn open(
+		serial_device: String,
+		config: Config,
+		baud_rate: u32,
+		exclusive: bool,
+	) -> Result<Self> {
+		Ok(Self {
+			connection: Connection::open(serial_device, config, baud_rate, exclusive)?,
 		})
 	}
 
+	/// Create a new instance from an already opened serial port.
+	///
+	/// The port should use the same settings as [`Self::new`], a baud rate of 921600 and a short read
+	/// timeout.
+	pub fn from_port(port: Box<dyn SerialPort>, config: Config) -> Self {
+		Self {
+			connection: Connection::new(port, config),
+		}
+	}
+
 	/// Finds the first available serial device with product name "Serial WS2812" and creates a new instance of this controller struct from it.
 	///
 	/// If more than one device is connected the returned device will be the first the OS lists.
 	pub fn find(config: Config) -> Result<Option<Self>> {
-		let ports = serialport::available_ports()?;
-		let mut serial_device = None;
-
-		for p in ports {
-			if let SerialPortType::UsbPort(usb) = p.port_type {
-				if usb.vid == DEVICE_VENDOR_ID || usb.pid == DEVICE_PRODUCT_ID {
-					serial_device = Some(p.port_name);
-				}
-			}
-		}
+		Self::find_where(config, is_device)
+	}
 
-		let Some(serial_device) = serial_device else {
+	/// Opens the first serial port the OS lists that matches the predicate, for example to pick a
+	/// device by its serial number or the USB port it is connected to.
+	///
+	/// [`is_device`] is the predicate [`Self::find`] uses.
+	pub fn find_where(
+		config: Config,
+		pred: impl Fn(&SerialPortInfo) -> bool,
+	) -> Result<Option<Self>> {
+		let Some(serial_device) = find_device_where(pred)? else {
 			return Ok(None);
 		};
 
 		Ok(Some(Self::new(serial_device, config)?))
 	}
 
-	fn reset_to_command(&mut self) -> Result<()> {
-		let mut buffer = [0u8; DEVICE_MESSAGE_TYPE_LEN * 4];
-
-		let mut has_printed = 0;
-		let mut counter = 0;
-
-		info!("trying to reset device to start of command");
-		self.port.set_timeout(Duration::from_millis(10))?;
-
-		loop {
-			let res = self.port.read(&mut buffer);
-			let read_bytes = match res {
-				Ok(n) => n,
-				Err(e) if e.kind() == io::ErrorKind::TimedOut => {
-					if has_printed == 0 {
-						info!("read timeout, writing null bytes to force a response");
-						has_printed += 1;
-					}
-
-					counter += 1;
-					if counter < 8 {
-						self.port.write_all(&[0u8])?;
-					} else {
-						self.port.write_all(&[0u8; 32])?;
-					}
-
-					continue;
-				}
-				Err(e) => return Err(e.into()),
-			};
-
-			// if we receive more than one byte we're probably in the branch that writes 32 bytes and need to repeat the process
-			if read_bytes > 1 {
-				counter = 0;
-				continue;
-			}
-
-			if &buffer[..1] == DEVICE_INIT_MESSAGE || &buffer[..1] == DEVICE_ERROR_MESSAGE {
-				break;
-			}
-		}
-
-		self.port.set_timeout(Duration::from_millis(50))?;
-		info!("reset successful");
+	/// Like [`Self::find`], but returns [`Error::DeviceNotFound`] if no device is connected.
+	pub fn find_required(config: Config) -> Result<Self> {
+		Self::find(config)?.ok_or(Error::DeviceNotFound)
+	}
 
-		Ok(())
+	/// The current configuration of the instance.
+	pub fn config(&self) -> &Config {
+		self.connection.config()
 	}
 
 	/// Sets the configuration for the instance.
 	pub fn set_config(&mut self, config: Config) -> Result<()> {
-		self.config = config;
-		self.configure()
+		block_on(self.connection.set_config(config))
 	}
 
 	pub fn configure(&mut self) -> Result<()> {
-		if !self.initialized {
-			self.reset_to_command()?;
-			self.initialized = true;
-		}
+		block_on(self.connection.configure())
+	}
 
-		self.send_command(
-			SET_STRIPS_MESSAGE,
-			&u32::to_le_bytes(self.config.strips as u32),
-		)?;
-		self.send_command(SET_LEDS_MESSAGE, &u32::to_le_bytes(self.config.leds as u32))?;
+	/// Like [`Self::configure`], but only sends the parts of the config that changed since it was
+	/// last sent, or nothing if it is the same.
+	///
+	/// Does the full [`Self::configure`] if the device wasn't configured yet, so it is cheap to call
+	/// before every frame.
+	pub fn ensure_configured(&mut self) -> Result<()> {
+		block_on(self.connection.ensure_configured())
+	}
 
-		Ok(())
+	/// Like [`Self::configure`], but retries the whole handshake up to `attempts` times with `backoff`
+	/// between the attempts, for devices that aren't ready right after they are plugged in.
+	///
+	/// The connection state is reset before every retry. Returns the last error if all attempts fail,
+	/// at least one attempt is always made.
+	pub fn configure_with_retries(&mut self, attempts: usize, backoff: Duration) -> Result<()> {
+		block_on(self.connection.configure_with_retries(attempts, backoff))
 	}
 
-	/// Send all bytes to the microcontroller, the length must be the configured amount of leds * strips * 3.
-	pub fn send_leds(&mut self, leds: &[u8]) -> Result<WriteResult> {
-		if !self.initialized {
-			self.configure()?;
-		}
+	/// Queries the limits and features of the device.
+	///
+	/// The result is cached, firmware that does not support the query is assumed to have
+	/// [`Capabilities::LEGACY`].
+	pub fn capabilities(&mut self) -> Result<Capabilities> {
+		block_on(self.connection.capabilities())
+	}
+
+	/// Reads the chip temperature and supply voltage of the device.
+	pub fn telemetry(&mut self) -> Result<Telemetry> {
+		block_on(self.connection.telemetry())
+	}
+
+	/// Reads the unique id of the device, the flash JEDEC id followed by the flash unique id.
+	///
+	/// This is the same id the USB serial number is derived from.
+	pub fn device_id(&mut self) -> Result<[u8; DEVICE_ID_LEN]> {
+		block_on(self.connection.device_id())
+	}
 
-		self.send_command(UPDATE_MESSAGE, leds)
+	/// Reads the strips, LEDs per strip and reversed strips the device is currently configured with
+	/// and uses them as the config of this instance, for boards where they aren't known up front.
+	///
+	/// The device forgets its config when it loses power, so this only matches the wiring if another
+	/// program or an earlier run configured it. The settings that only exist on the host, like
+	/// [`Config::rgbw_mode`], are left at their defaults. Returns
+	/// [`Error::UnsupportedCommand`] for firmware without [`crate::FEATURE_GET_CONFIG`].
+	pub fn load_config_from_device(&mut self) -> Result<Config> {
+		block_on(self.connection.load_config_from_device())
 	}
 
-	fn send_command(&mut self, command: &[u8], data: &[u8]) -> Result<WriteResult> {
-		let mut output = [0u8; DEVICE_MESSAGE_TYPE_LEN];
+	/// Sends `frames` frames as fast as the device takes them and reports how long they took, to
+	/// check what a host, cable and config can do.
+	///
+	/// The frames cycle through dim shades of white, the last one stays on the LEDs. Every frame is
+	/// timed from sending it until the device acknowledged it, with the clock of
+	/// [`Self::set_clock`].
+	pub fn measure_throughput(&mut self, frames: usize) -> Result<ThroughputReport> {
+		block_on(self.connection.measure_throughput(frames))
+	}
 
-		#[cfg(feature = "timings")]
-		let command_start = Instant::now();
+	/// Runs through everything the device does, the "is my hardware ok" check.
+	///
+	/// Configures the device from scratch, reads its capabilities, id and telemetry, shows red, green,
+	/// blue and white on every LED for half a second each and measures the frame rate with black
+	/// frames, which leaves the LEDs off. Only fails if the device can't be configured or reset, the
+	/// frames that weren't acknowledged are in the report.
+	pub fn self_test(&mut self) -> Result<SelfTestReport> {
+		block_on(self.connection.self_test())
+	}
 
-		if self.serial_write(command)? != command.len() {
-			return Err(Error::IncompleteWrite);
-		}
-		if self.port.read(&mut output)? != 1 {
-			return Err(Error::NoResponse);
-		}
-		if &output != DEVICE_PARTIAL_MESSAGE {
-			return Err(Error::UnexpectedResponse {
-				expected: String::from_utf8_lossy(DEVICE_PARTIAL_MESSAGE).to_string(),
-				received: format!("{:?}", output),
-			});
-		}
+	/// Checks that the device is still responding.
+	///
+	/// Can be sent at any time between other commands, so it is also useful to keep an idle
+	/// connection alive.
+	pub fn ping(&mut self) -> Result<()> {
+		block_on(self.connection.ping())
+	}
 
-		#[cfg(feature = "timings")]
-		let data_start = Instant::now();
+	/// The amount of bytes [`Self::send_leds`] expects for the current config.
+	pub fn transfer_size(&self) -> usize {
+		self.connection.transfer_size()
+	}
 
-		if self.serial_write(data)? != data.len() {
-			return Err(Error::IncompleteWrite);
-		}
-		if self.port.read(&mut output)? != 1 {
-			return Err(Error::NoResponse);
-		}
-		if &output != DEVICE_OK_MESSAGE {
-			return Err(Error::UnexpectedResponse {
-				expected: String::from_utf8_lossy(DEVICE_OK_MESSAGE).to_string(),
-				received: format!("{:?}", output),
-			});
-		}
+	/// Waits until the last frame was written to the LEDs.
+	///
+	/// The device acknowledges frames as soon as it received them, the output to the LEDs happens
+	/// afterwards. This adds a round trip plus the time the output takes, about 30µs per LED on the
+	/// longest strip and the 280µs reset period, or twice that if the previous frame was still being
+	/// written.
+	pub fn wait_until_displayed(&mut self) -> Result<()> {
+		block_on(self.connection.wait_until_displayed())
+	}
+
+	/// Opens the serial port again, for example after the device was unplugged.
+	///
+	/// The port is opened by the name the controller was created with, or the device is searched
+	/// again for controllers created from an already open port. The device starts with its default
+	/// config, so it is configured again and ready for [`Self::send_leds`] right away, unless that is
+	/// turned off with [`Self::set_configure_on_reconnect`].
+	pub fn reconnect(&mut self) -> Result<()> {
+		block_on(self.connection.reconnect())
+	}
+
+	/// Whether [`Self::reconnect`] configures the device right away, enabled by default.
+	///
+	/// Without it the device keeps its default config until the next frame or an explicit
+	/// [`Self::configure`], for example to change the config before the first one is sent.
+	pub fn set_configure_on_reconnect(&mut self, configure: bool) {
+		self.connection.set_configure_on_reconnect(configure)
+	}
 
-		#[cfg(feature = "timings")]
-		let end = Instant::now();
+	/// What was sent since the controller was created or [`Self::reset_stats`] was called.
+	pub fn stats(&self) -> ControllerStats {
+		self.connection.stats()
+	}
 
-		#[cfg(feature = "timings")]
-		return Ok((data_start - command_start, end - data_start));
+	pub fn reset_stats(&mut self) {
+		self.connection.reset_stats()
+	}
+
+	/// Measures the timings of commands and the self test with `clock` instead of
+	/// [`std::time::Instant`], for hosts where another monotonic time source is more reliable.
+	///
+	/// `clock` returns the time since any fixed point, only the differences are used.
+	pub fn set_clock(&mut self, clock: impl Fn() -> Duration + Send + 'static) {
+		self.connection.set_clock(clock)
+	}
+
+	/// Scales the red, green and blue channel of every LED before it is sent, for example to white
+	/// balance strips where one color is brighter than the others.
+	///
+	/// 255 leaves a channel as it is, which is the default, and 0 turns it off. The white channel of
+	/// RGBW strips isn't affected.
+	pub fn set_channel_scale(&mut self, r: u8, g: u8, b: u8) {
+		self.connection.set_channel_scale(r, g, b)
+	}
+
+	/// Lets [`Self::send_leds`] skip frames that are the same as the last one it sent, compared by
+	/// their [`Frame::checksum`].
+	///
+	/// Any other command sent in between, a reconnect or a change of the channel scale makes the next
+	/// frame get sent regardless. Calling this again, even with the same value, does as well, which
+	/// can be used to force a resend. Disabled by default.
+	pub fn set_skip_unchanged(&mut self, skip: bool) {
+		self.connection.set_skip_unchanged(skip)
+	}
+
+	/// Enables or disables the temporal dithering of [`Self::send_leds_u16`], enabled by default.
+	///
+	/// Without dithering every channel is rounded to the closest 8 bit value.
+	pub fn set_dithering(&mut self, enabled: bool) {
+		self.connection.set_dithering(enabled)
+	}
+
+	/// Lets [`Self::send_leds`] get the device back to the start of a command and send the frame
+	/// again up to `retries` times if the device didn't respond or responded with something
+	/// unexpected, before returning the error.
+	///
+	/// The default is 0, which returns the first error.
+	pub fn set_frame_retries(&mut self, retries: usize) {
+		self.connection.set_frame_retries(retries)
+	}
+
+	/// Lets [`Self::send_leds`] try to turn off every LED when sending a frame failed, before
+	/// returning the error, so a broken frame doesn't stay on the LEDs.
+	///
+	/// The device is reset to the start of a command first, if that or the blank frame fails as well
+	/// the original error is returned regardless. Disabled by default.
+	pub fn set_blank_on_error(&mut self, blank: bool) {
+		self.connection.set_blank_on_error(blank)
+	}
+
+	/// Sends every frame with a checksum, so the device can detect frames that got corrupted on the
+	/// way and have them sent again, which [`ControllerStats::resends`] counts.
+	///
+	/// A frame is resent once before [`Error::ChecksumMismatch`] is returned. Requires a device with
+	/// [`FEATURE_UPDATE_CHECKSUM`], returns [`Error::UnsupportedCommand`] otherwise. Disabled by
+	/// default.
+	pub fn set_update_checksums(&mut self, enabled: bool) -> Result<()> {
+		block_on(self.connection.set_update_checksums(enabled))
+	}
+
+	/// Run-length encodes frames with runs of the same color, like mostly black frames, which takes
+	/// less bandwidth than sending every LED.
+	///
+	/// Every frame is sent as it is if the encoding isn't smaller, and with
+	/// [`Self::set_update_checksums`] enabled, which takes precedence. Requires a device with
+	/// [`FEATURE_RLE_UPDATE`], returns [`Error::UnsupportedCommand`] otherwise. Disabled by default.
+	pub fn set_rle_updates(&mut self, enabled: bool) -> Result<()> {
+		block_on(self.connection.set_rle_updates(enabled))
+	}
+
+	/// Enables or disables a strip, disabled strips stay dark regardless of their LED data.
+	///
+	/// Takes effect with the next frame. The setting is kept on the controller and sent again when
+	/// the device is configured, so it survives reconnects.
+	pub fn set_strip_enabled(&mut self, strip: usize, enabled: bool) -> Result<()> {
+		block_on(self.connection.set_strip_enabled(strip, enabled))
+	}
+
+	/// Drives only the first `len` LEDs of `strip` and sends the change to the device, for strips that
+	/// are shorter than [`Config::leds`], see [`Config::strip_lengths`].
+	///
+	/// The other strips keep their length. The LEDs past the end are turned off while longer strips
+	/// are written and aren't written at all past the end of the longest strip, so they never show
+	/// stale data. The LED data of the strip is only `len` LEDs long afterwards.
+	///
+	/// Lengths the device doesn't support are rejected with [`Error::InvalidConfig`]. If sending
+	/// fails otherwise the length stays in the config and is sent the next time the device is
+	/// configured.
+	pub fn set_strip_length(&mut self, strip: usize, len: usize) -> Result<()> {
+		block_on(self.connection.set_strip_length(strip, len))
+	}
 
-		#[cfg(not(feature = "timings"))]
+	/// Sets the order `strip` expects the color channels in and sends it to the device, see
+	/// [`Config::color_orders`].
+	///
+	/// Orders the device doesn't support are rejected with [`Error::InvalidConfig`]. If sending
+	/// fails otherwise the order stays in the config and is sent the next time the device is
+	/// configured.
+	pub fn set_strip_color_order(&mut self, strip: usize, order: ColorOrder) -> Result<()> {
+		block_on(self.connection.set_strip_color_order(strip, order))
+	}
+
+	/// Sets how long the outputs are kept low after a frame so the LEDs latch it.
+	///
+	/// Defaults to 280µs, some clones and the SK6812 need a longer reset to latch reliably. Has to be
+	/// between [`MIN_RESET_DURATION_US`] and [`MAX_RESET_DURATION_US`] microseconds, longer resets
+	/// lower the maximum frame rate. Like [`Self::set_strip_enabled`] it survives reconnects.
+	pub fn set_reset_duration(&mut self, duration: Duration) -> Result<()> {
+		block_on(self.connection.set_reset_duration(duration))
+	}
+
+	/// Selects the bit timing for other LED chips than the WS2812.
+	///
+	/// Applies from the next frame on. Like [`Self::set_strip_enabled`] it survives reconnects.
+	pub fn set_led_timing(&mut self, timing: LedTiming) -> Result<()> {
+		block_on(self.connection.set_led_timing(timing))
+	}
+
+	/// Send all bytes to the microcontroller, the length must be the configured amount of leds * 3.
+	///
+	/// The LEDs are in the order of [`Config::buffer_layout`] and reordered to the strip-major order
+	/// of the device if needed.
+	///
+	/// The device is configured first if the config didn't reach it yet, for example when
+	/// [`Self::set_config`] failed, so frames are only sent once the device expects their size.
+	pub fn send_leds(&mut self, leds: impl AsRef<[u8]>) -> Result<WriteResult> {
+		block_on(self.connection.send_leds(leds.as_ref()))
+	}
+
+	/// Like [`Self::send_leds`], but always strip-major as that is the layout of a [`Frame`],
+	/// regardless of [`Config::buffer_layout`].
+	pub fn send_frame(&mut self, frame: &Frame) -> Result<WriteResult> {
+		block_on(self.connection.send_frame(frame))
+	}
+
+	/// Like [`Self::send_leds`], but takes the buffer and returns it once the frame is sent, so it
+	/// can be reused for the next frame without allocating.
+	pub fn send_leds_owned(&mut self, leds: Vec<u8>) -> Result<(WriteResult, Vec<u8>)> {
+		block_on(self.connection.send_leds_owned(leds))
+	}
+
+	/// Like [`Self::send_leds`], but with one color per LED, in the same order as the bytes.
+	///
+	/// The colors are passed to the device as they are, without a copy.
+	#[cfg(feature = "bytemuck")]
+	pub fn send_rgb(&mut self, pixels: &[RGB]) -> Result<WriteResult> {
+		self.send_leds(bytemuck::cast_slice(pixels))
+	}
+
+	/// Like [`Self::send_leds`], but with 16 bits per channel, one color per LED in the same order as
+	/// the bytes.
+	///
+	/// The colors are scaled down to 8 bits with temporal dithering, what is cut off in one frame is
+	/// added to the next one, which keeps slow fades and dark gradients smooth instead of stepping
+	/// through the 8 bit values. This only works if frames are sent continuously, see
+	/// [`Self::set_dithering`] to round instead.
+	pub fn send_leds_u16(&mut self, pixels: &[[u16; 3]]) -> Result<WriteResult> {
+		block_on(self.connection.send_leds_u16(pixels))
+	}
+
+	/// Sends one color per LED, with every strip taking up as many LEDs as the longest strip.
+	///
+	/// LED `led` of strip `strip` is at `pixels[strip * longest + led]`, so `pixels` has to have
+	/// `strips * leds` entries when all strips have the same length. With
+	/// [`Config::strip_lengths`] the device expects every strip to only take up its own length like
+	/// in a [`Frame`], the entries past the end of shorter strips are dropped. Otherwise this sends
+	/// the same as [`Self::send_leds`] with the colors as bytes.
+	pub fn send_flat(&mut self, pixels: &[RGB]) -> Result<WriteResult> {
+		block_on(self.connection.send_flat(pixels))
+	}
+
+	/// Like [`Self::send_leds`], but only returns once the frame is on the LEDs.
+	///
+	/// See [`Self::wait_until_displayed`] for the added latency, use [`Self::send_leds`] if the exact
+	/// time the LEDs change doesn't matter.
+	pub fn send_leds_blocking(&mut self, leds: &[u8]) -> Result<WriteResult> {
+		block_on(self.connection.send_leds_blocking(leds))
+	}
+
+	/// Send only the LEDs that changed between `prev` and `next`.
+	///
+	/// `prev` has to be the frame that was sent to the device last. If more than half of the LEDs
+	/// changed the full frame is sent with [`Self::send_frame`] instead.
+	pub fn send_diff(&mut self, prev: &Frame, next: &Frame) -> Result<WriteResult> {
+		block_on(self.connection.send_diff(prev, next))
+	}
+
+	/// Runs the animation at `fps` frames per second until it is finished or sending a frame fails.
+	///
+	/// Frames that take longer than the interval delay the following ones instead of being skipped,
+	/// with 0 fps every frame is sent as soon as the device takes it.
+	pub fn run_animation(&mut self, animation: impl Animation, fps: u32) -> Result<()> {
+		block_on(self.connection.run_animation(animation, fps))
+	}
+
+	/// Sends a command that the typed methods don't cover, for example to try out new commands or
+	/// vendor extensions of the firmware.
+	///
+	/// Nothing is validated, `data` has to be exactly what the device expects for the command or it
+	/// ends up waiting for more data or treating the rest as the next command. Queries can't be sent
+	/// this way, as the device responds to them differently. Commands that change the config of the
+	/// device are not sent again by [`Self::configure`].
+	pub fn send_raw(
+		&mut self,
+		command: &[u8; MESSAGE_TYPE_LEN],
+		data: &[u8],
+	) -> Result<WriteResult> {
+		block_on(self.connection.send_raw(command, data))
+	}
+
+	/// Sends the vendor command `id` of a fork of the firmware, see [`VENDOR_COMMAND_PREFIX`].
+	///
+	/// The length is sent in front of `data`, so unlike [`Self::send_raw`] the device always knows
+	/// where the command ends. Firmware that doesn't know the id responds with an error.
+	pub fn send_vendor_command(
+		&mut self,
+		id: &[u8; VENDOR_ID_LEN],
+		data: &[u8],
+	) -> Result<WriteResult> {
+		block_on(self.connection.send_vendor_command(id, data))
+	}
+
+	/// Reboots the device into the UF2 bootloader to update the firmware.
+	///
+	/// The device disconnects afterwards and shows up as a mass storage device.
+	pub fn reboot_to_bootloader(&mut self) -> Result<()> {
+		block_on(self.connection.reboot_to_bootloader())
+	}
+
+	/// Sets the DTR (data terminal ready) line of the serial port.
+	///
+	/// What the line does depends on the board, some reset or enter their bootloader when it is
+	/// toggled like an Arduino, the RP2040 ignores it. The controller doesn't know what happened to
+	/// the device, [`Self::reconnect`] afterwards if it was reset.
+	pub fn set_dtr(&mut self, level: bool) -> Result<()> {
+		self.connection.set_dtr(level)
+	}
+
+	/// Sets the RTS (request to send) line of the serial port, like [`Self::set_dtr`] what it does
+	/// depends on the board.
+	pub fn set_rts(&mut self, level: bool) -> Result<()> {
+		self.connection.set_rts(level)
+	}
+
+	/// Reads the CTS (clear to send) line of the serial port, the outputs set with
+	/// [`Self::set_dtr`] and [`Self::set_rts`] can't be read back.
+	pub fn clear_to_send(&mut self) -> Result<bool> {
+		self.connection.clear_to_send()
+	}
+
+	/// Reads the DSR (data set ready) line of the serial port.
+	pub fn data_set_ready(&mut self) -> Result<bool> {
+		self.connection.data_set_ready()
+	}
+
+	/// Switches the device into streaming mode.
+	///
+	/// While streaming, frames are sent with [`Self::send_frame_streamed`] without the per frame command
+	/// and the device only acknowledges every `ack_interval` frames. No other commands can be sent until
+	/// the stream is ended with [`Self::end_stream`].
+	pub fn begin_stream(&mut self, ack_interval: u32) -> Result<()> {
+		block_on(self.connection.begin_stream(ack_interval))
+	}
+
+	/// Send a single frame in streaming mode, the length must be the configured amount of leds * 3.
+	pub fn send_frame_streamed(&mut self, leds: &[u8]) -> Result<()> {
+		block_on(self.connection.send_frame_streamed(leds))
+	}
+
+	/// Leaves streaming mode.
+	pub fn end_stream(&mut self) -> Result<()> {
+		block_on(self.connection.end_stream())
+	}
+}
+
+impl Ws2812Controller for SerialWs2812 {
+	fn config(&self) -> &Config {
+		self.config()
+	}
+
+	fn set_config(&mut self, config: Config) -> Result<()> {
+		self.set_config(config)
+	}
+
+	fn configure(&mut self) -> Result<()> {
+		self.configure()
+	}
+
+	fn transfer_size(&self) -> usize {
+		self.transfer_size()
+	}
+
+	fn send_leds(&mut self, leds: &[u8]) -> Result<WriteResult> {
+		self.send_leds(leds)
+	}
+}
+
+/// Every read and write blocks until it is done or the timeout of the port passed.
+impl Transport for Box<dyn SerialPort> {
+	type Port = dyn SerialPort;
+
+	fn open(serial_device: &str, baud_rate: u32, exclusive: bool) -> Result<Self> {
+		open_port(serial_device, baud_rate, exclusive)
+	}
+
+	fn reopen(&mut self, serial_device: &str, baud_rate: u32, exclusive: bool) -> Result<()> {
+		*self = open_port(serial_device, baud_rate, exclusive)?;
 		Ok(())
 	}
 
-	fn serial_write(&mut self, buffer: &[u8]) -> Result<usize> {
-		match self.port.write_all(buffer) {
-			Ok(_) => Ok(buffer.len()),
-			// Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
-			// 	println!("WARNING: serial timeout");
-			// 	Ok(0)
-			// }
-			// Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
-			// 	println!("WARNING: serial interrupted");
-			// 	Ok(0)
-			// }
-			Err(e) => Err(e.into()),
-		}
+	fn port(&mut self) -> &mut Self::Port {
+		&mut **self
+	}
+
+	async fn write_all(&mut self, buffer: &[u8]) -> io::Result<()> {
+		Write::write_all(self, buffer)
+	}
+
+	async fn read(&mut self, buffer: &mut [u8], _: Duration) -> io::Result<usize> {
+		Read::read(self, buffer)
+	}
+
+	async fn read_exact(&mut self, buffer: &mut [u8], _: Duration) -> io::Result<()> {
+		Read::read_exact(self, buffer)
+	}
+
+	async fn sleep(duration: Duration) {
+		thread::sleep(duration);
+	}
+}
+
+fn open_port(serial_device: &str, baud_rate: u32, exclusive: bool) -> Result<Box<dyn SerialPort>> {
+	let builder = serialport::new(serial_device, baud_rate)
+		.timeout(protocol::TIMEOUT)
+		.exclusive(exclusive);
+
+	builder
+		.open()
+		.map_err(|e| protocol::open_error(serial_device, e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_port::FakePort;
+
+	#[test]
+	fn mismatched_first_frame_is_not_sent() {
+		let port = FakePort::new();
+		let mut controller = SerialWs2812::from_port(Box::new(port.clone()), Config::strips(2, 4));
+
+		let leds = vec![0; controller.transfer_size() - 3];
+		let result = controller.send_leds(leds);
+
+		assert!(matches!(
+			result,
+			Err(Error::BufferSizeMismatch {
+				expected: 24,
+				received: 21,
+			})
+		));
+		assert!(port.written().is_empty());
+	}
+
+	#[test]
+	fn first_frame_configures_the_device() {
+		let port = FakePort::new();
+		let mut controller = SerialWs2812::from_port(Box::new(port.clone()), Config::strips(2, 4));
+
+		controller.send_leds([0; 24]).unwrap();
+
+		assert!(port.written().ends_with(&[0; 24]));
+	}
+
+	#[test]
+	fn probing_no_baud_rates_is_its_own_error() {
+		let result = SerialWs2812::probe_baud_rates("fake", &[], Config::default());
+
+		assert!(matches!(result, Err(Error::NoBaudRates)));
+	}
+
+	#[test]
+	fn full_frame_spans_many_packets() {
+		let port = FakePort::new();
+		let config = Config::strips(MAX_STRIPS, MAX_LEDS_PER_STRIP);
+		let mut controller = SerialWs2812::from_port(Box::new(port.clone()), config);
+
+		let leds: Vec<u8> = (0..controller.transfer_size()).map(|i| i as u8).collect();
+		controller.send_leds(&leds).unwrap();
+
+		let mut update = serial_ws2812_shared::UPDATE_MESSAGE.to_vec();
+		update.extend_from_slice(&leds);
+		assert!(port.written().ends_with(&update));
+		assert!(port.packets() > leds.len() / 64);
+		assert_eq!(port.frames(), [leds]);
+	}
+
+	#[test]
+	fn silent_device_is_no_response() {
+		let mut controller =
+			SerialWs2812::from_port(Box::new(FakePort::silent()), Config::default());
+
+		assert!(matches!(
+			block_on(controller.connection.read_device_message()),
+			Err(Error::NoResponse)
+		));
+	}
+
+	#[test]
+	fn empty_read_is_retried() {
+		let port = FakePort::new();
+		port.stall(1);
+		let mut controller = SerialWs2812::from_port(Box::new(port), Config::default());
+
+		let response = block_on(controller.connection.read_device_message()).unwrap();
+
+		assert_eq!(&response, serial_ws2812_shared::DEVICE_INIT_MESSAGE);
+	}
+
+	#[test]
+	fn closed_port_is_an_error() {
+		let mut controller =
+			SerialWs2812::from_port(Box::new(FakePort::closed()), Config::default());
+
+		let result = block_on(controller.connection.read_device_message());
+
+		assert!(matches!(result, Err(Error::IO(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
 	}
 }
@@ -0,0 +1,38 @@
+//! Logging through `tracing` with the `tracing` feature, without it the macros only check their
+//! arguments and never evaluate them.
+//!
+//! Every command, every response of the device and the data in between is logged at the `trace`
+//! level, to debug the host and the firmware getting out of sync.
+
+use std::time::Duration;
+
+// not every module is always compiled
+#[cfg(feature = "tracing")]
+#[allow(unused_imports)]
+pub(crate) use tracing::{debug, info, trace, warn};
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! noop {
+	($($arg:tt)*) => {{
+		if false {
+			let _ = format_args!($($arg)*);
+		}
+	}};
+}
+
+#[cfg(not(feature = "tracing"))]
+#[allow(unused_imports)]
+pub(crate) use {noop as debug, noop as info, noop as trace, noop as warn};
+
+/// Records how long the parts of a command took on the current span.
+pub(crate) fn record_timings(command: Duration, data: Duration) {
+	#[cfg(feature = "tracing")]
+	{
+		let span = tracing::Span::current();
+		span.record("command_us", command.as_micros() as u64);
+		span.record("data_us", data.as_micros() as u64);
+	}
+
+	#[cfg(not(feature = "tracing"))]
+	let _ = (command, data);
+}
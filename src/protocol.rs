@@ -0,0 +1,1071 @@
+//! Backend independent parts of the serial protocol.
+//!
+//! The blocking and the tokio controller only differ in how they talk to the serial port, everything
+//! that decides what gets written and how responses are interpreted lives here. The state of a
+//! controller and the order it sends commands in is in [`crate::connection`].
+
+use std::{io, time::Duration};
+
+use serial_ws2812_shared::{
+	crc32,
+	CAPABILITIES_LEN,
+	COLOR_ORDER_GRB,
+	CONFIG_LEN,
+	DEVICE_ERROR_MESSAGE,
+	DEVICE_INIT_MESSAGE,
+	DEVICE_MESSAGE_TYPE_LEN,
+	DEVICE_OK_MESSAGE,
+	DEVICE_PARTIAL_MESSAGE,
+	DEVICE_PRODUCT_ID,
+	DEVICE_PRODUCT_NAME,
+	DEVICE_RESEND_MESSAGE,
+	DEVICE_RESYNC_MESSAGE,
+	DEVICE_VENDOR_ID,
+	DIFF_MESSAGE,
+	MAX_STRIPS,
+	MESSAGE_NUM_LEN,
+	MESSAGE_TYPE_LEN,
+	RLE_RUN_LEN,
+	RLE_UPDATE_MESSAGE,
+	SET_COLOR_ORDER_MESSAGE,
+	SET_LEDS_MESSAGE,
+	SET_REVERSED_MESSAGE,
+	SET_STRIPS_MESSAGE,
+	SET_STRIP_LENGTHS_MESSAGE,
+	STRIP_LENGTHS_LEN,
+	TELEMETRY_LEN,
+	UPDATE_CHECKED_MESSAGE,
+	UPDATE_MESSAGE,
+};
+use serialport::{SerialPortInfo, SerialPortType};
+
+use crate::{
+	color::{scale8, NO_CHANNEL_SCALE},
+	log::{self, info, trace, warn},
+	rgbw::{self, BYTES_PER_RGBW_LED},
+	BufferLayout,
+	Capabilities,
+	Config,
+	Error,
+	Frame,
+	Result,
+	Telemetry,
+	WriteResult,
+	BYTES_PER_LED,
+	RGB,
+};
+
+/// The default baud rate, USB serial devices usually ignore it.
+pub(crate) const BAUD_RATE: u32 = 921_600;
+
+/// Timeout used for regular commands.
+pub(crate) const TIMEOUT: Duration = Duration::from_millis(50);
+/// Timeout used while trying to get the device back to the start of a command.
+pub(crate) const RESET_TIMEOUT: Duration = Duration::from_millis(10);
+/// How often a frame the device received corrupted is sent again before giving up.
+pub(crate) const MAX_RESENDS: usize = 1;
+
+/// Turns the error of opening a port into [`Error::PortUnavailable`] if it's most likely held by
+/// someone else.
+///
+/// `serialport` reports a port that is locked the same way as one that is gone, so this can't be
+/// told apart.
+pub(crate) fn open_error(serial_device: &str, error: serialport::Error) -> Error {
+	match error.kind {
+		serialport::ErrorKind::NoDevice => Error::PortUnavailable(serial_device.to_owned()),
+		_ => Error::SerialPort(error),
+	}
+}
+
+/// Checks if the port belongs to a serial to ws2812 device.
+///
+/// Matches on the vendor id, the product id or the product name, some platforms replace spaces in
+/// the product name with underscores. This is the predicate [`SerialWs2812::find`] uses, it can
+/// be combined with other checks for [`SerialWs2812::find_where`].
+///
+/// [`SerialWs2812::find`]: crate::SerialWs2812::find
+/// [`SerialWs2812::find_where`]: crate::SerialWs2812::find_where
+pub fn is_device(port: &SerialPortInfo) -> bool {
+	let SerialPortType::UsbPort(usb) = &port.port_type else {
+		return false;
+	};
+
+	if usb.vid == DEVICE_VENDOR_ID || usb.pid == DEVICE_PRODUCT_ID {
+		return true;
+	}
+
+	usb.product.as_deref().is_some_and(|product| {
+		product == DEVICE_PRODUCT_NAME || product == DEVICE_PRODUCT_NAME.replace(' ', "_")
+	})
+}
+
+/// Returns the name of the first matching serial device the OS lists.
+pub(crate) fn find_device() -> Result<Option<String>> {
+	find_device_where(is_device)
+}
+
+/// Returns the name of the first serial port the OS lists that matches the predicate.
+pub(crate) fn find_device_where(pred: impl Fn(&SerialPortInfo) -> bool) -> Result<Option<String>> {
+	let ports = serialport::available_ports()?;
+
+	Ok(ports.into_iter().find(|p| pred(p)).map(|p| p.port_name))
+}
+
+/// Checks if at least one matching serial device is connected, without opening it.
+pub fn is_device_present() -> Result<bool> {
+	Ok(find_device()?.is_some())
+}
+
+/// Returns the names of all matching serial devices, in the order the OS lists them.
+pub fn list_devices() -> Result<Vec<String>> {
+	let ports = serialport::available_ports()?;
+
+	Ok(ports
+		.into_iter()
+		.filter(is_device)
+		.map(|p| p.port_name)
+		.collect())
+}
+
+/// A connected serial to ws2812 device with what it reports over USB, see
+/// [`list_devices_detailed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+	pub port_name:     String,
+	/// Unique for every board, to tell multiple devices apart across reconnects.
+	pub serial_number: Option<String>,
+	pub manufacturer:  Option<String>,
+	pub product:       Option<String>,
+}
+
+/// Like [`list_devices`], but with the USB serial number, manufacturer and product of every
+/// device, for example to let the user pick one.
+pub fn list_devices_detailed() -> Result<Vec<DeviceInfo>> {
+	let ports = serialport::available_ports()?;
+
+	Ok(ports
+		.into_iter()
+		.filter(is_device)
+		.filter_map(|port| match port.port_type {
+			SerialPortType::UsbPort(usb) => Some(DeviceInfo {
+				port_name:     port.port_name,
+				serial_number: usb.serial_number,
+				manufacturer:  usb.manufacturer,
+				product:       usb.product,
+			}),
+			_ => None,
+		})
+		.collect())
+}
+
+/// Bitmask with every strip enabled, the default of the device.
+pub(crate) const ALL_STRIPS: u32 = u32::MAX;
+
+/// A readable name of a command for logs.
+pub(crate) fn command_name(command: &[u8]) -> &'static str {
+	use serial_ws2812_shared::{parse_command, ParseStep};
+
+	match parse_command(command) {
+		ParseStep::Command(command) => command.name(),
+		ParseStep::NeedMore | ParseStep::Error => "unknown",
+	}
+}
+
+/// A command and its data.
+pub(crate) type ConfigCommand = (&'static [u8; MESSAGE_TYPE_LEN], Vec<u8>);
+
+/// The commands (and their data) that have to be sent to apply the config to the device.
+pub(crate) fn config_commands(config: &Config) -> Vec<ConfigCommand> {
+	let leds = config.device_strip_lens().max().unwrap_or(0);
+	let mut commands = vec![
+		(
+			SET_STRIPS_MESSAGE,
+			u32::to_le_bytes(config.strips as u32).to_vec(),
+		),
+		(SET_LEDS_MESSAGE, u32::to_le_bytes(leds as u32).to_vec()),
+	];
+
+	if config.strip_lengths.is_some() {
+		let mut data = vec![0; STRIP_LENGTHS_LEN];
+		for (bytes, len) in data.chunks_exact_mut(2).zip(config.device_strip_lens()) {
+			bytes.copy_from_slice(&(len as u16).to_le_bytes());
+		}
+		commands.push((SET_STRIP_LENGTHS_MESSAGE, data));
+	}
+
+	// the device resets these when the strips are set, only needed if they aren't the default
+	let reversed = config.reversed_mask();
+	if reversed != 0 {
+		commands.push((SET_REVERSED_MESSAGE, u32::to_le_bytes(reversed).to_vec()));
+	}
+	let color_orders = config.color_order_ids();
+	if color_orders != [COLOR_ORDER_GRB; MAX_STRIPS] {
+		commands.push((SET_COLOR_ORDER_MESSAGE, color_orders.to_vec()));
+	}
+
+	commands
+}
+
+/// The part of `commands` that has to be sent to a device that already received `sent`.
+///
+/// Every command is sent again from the first one that changed, as later commands depend on the
+/// earlier ones: [`SET_STRIPS_MESSAGE`] resets the reversed strips and [`SET_LEDS_MESSAGE`] overrides
+/// the strip lengths. A command that is no longer sent can only be undone by those, so everything
+/// is sent again in that case.
+pub(crate) fn changed_config_commands<'a>(
+	sent: &[ConfigCommand],
+	commands: &'a [ConfigCommand],
+) -> &'a [ConfigCommand] {
+	let removed = sent
+		.iter()
+		.any(|(sent, _)| !commands.iter().any(|(command, _)| command == sent));
+	if removed {
+		return commands;
+	}
+
+	let unchanged = sent
+		.iter()
+		.zip(commands)
+		.take_while(|(sent, command)| sent == command)
+		.count();
+
+	&commands[unchanged..]
+}
+
+/// Converts pixels where every strip takes up as many LEDs as the longest one to the strip-major
+/// layout the device expects, where every strip only takes up its own length.
+pub(crate) fn flat_to_strip_major(config: &Config, pixels: &[RGB]) -> Result<Vec<u8>> {
+	let stride = config.strip_lens().max().unwrap_or(0);
+	let expected = config.strips * stride;
+	if pixels.len() != expected {
+		return Err(Error::BufferSizeMismatch {
+			expected: expected * BYTES_PER_LED,
+			received: pixels.len() * BYTES_PER_LED,
+		});
+	}
+
+	Ok(pixels
+		.chunks(stride.max(1))
+		.zip(config.strip_lens())
+		.flat_map(|(strip, len)| &strip[..len])
+		.flat_map(|&pixel| <[u8; BYTES_PER_LED]>::from(pixel))
+		.collect())
+}
+
+/// The index of every LED in a buffer in `layout`, in the strip-major order the device expects.
+fn strip_major_order(config: &Config, layout: BufferLayout) -> impl Iterator<Item = usize> {
+	let mut lens = [0; MAX_STRIPS];
+	for (len, strip_len) in lens.iter_mut().zip(config.strip_lens()) {
+		*len = strip_len;
+	}
+	let strips = config.strips.min(MAX_STRIPS);
+
+	(0..strips).flat_map(move |strip| {
+		let start = lens[..strip].iter().sum::<usize>();
+		// LED-major buffers only have the LEDs of the strips that are long enough at every index
+		let mut before = 0;
+		(0..lens[strip]).map(move |led| match layout {
+			BufferLayout::StripMajor => start + led,
+			BufferLayout::LedMajor => {
+				let index = before + lens[..strip].iter().filter(|&&len| len > led).count();
+				before += lens[..strips].iter().filter(|&&len| len > led).count();
+				index
+			}
+		})
+	})
+}
+
+/// Reorders LEDs in `layout` to the strip-major order the device expects, `leds` has to be
+/// [`Config::buffer_size`] long.
+#[cfg(feature = "simulator")]
+pub(crate) fn to_strip_major(config: &Config, layout: BufferLayout, leds: &[u8]) -> Vec<u8> {
+	let bytes_per_led = config.bytes_per_led();
+
+	strip_major_order(config, layout)
+		.flat_map(|index| &leds[index * bytes_per_led..(index + 1) * bytes_per_led])
+		.copied()
+		.collect()
+}
+
+/// Whether [`encode_leds`] changes anything, the LEDs can be sent as they are otherwise.
+pub(crate) fn needs_encoding(config: &Config, layout: BufferLayout, scale: [u8; 3]) -> bool {
+	layout != BufferLayout::StripMajor || scale != NO_CHANNEL_SCALE || config.rgbw_mode.is_some()
+}
+
+/// Reorders the LEDs from `layout`, applies the channel scale and converts them to what the device
+/// expects in a single pass, into `out`.
+///
+/// `out` is cleared first, reusing it between frames avoids allocating.
+pub(crate) fn encode_leds(
+	config: &Config,
+	layout: BufferLayout,
+	scale: [u8; 3],
+	leds: &[u8],
+	out: &mut Vec<u8>,
+) {
+	let bytes_per_led = config.bytes_per_led();
+	let colors = strip_major_order(config, layout).map(|index| {
+		let mut color = [0; BYTES_PER_RGBW_LED];
+		color[..bytes_per_led].copy_from_slice(&leds[index * bytes_per_led..][..bytes_per_led]);
+		// white isn't scaled, `scale` only has red, green and blue
+		for (channel, scale) in color.iter_mut().zip(scale) {
+			*channel = scale8(*channel, scale);
+		}
+		color
+	});
+
+	out.clear();
+	out.reserve(config.frame_bytes());
+	match config.rgbw_mode {
+		Some(conversion) => rgbw::encode_into(config, conversion, colors, out),
+		None => {
+			for color in colors {
+				out.extend_from_slice(&color[..bytes_per_led]);
+			}
+		}
+	}
+}
+
+/// Encodes the changes between two frames as data for the diff command.
+///
+/// Returns `None` if the frames have different sizes or more than half of the LEDs changed, sending the
+/// full frame is the better option in that case.
+pub(crate) fn encode_diff(prev: &Frame, next: &Frame) -> Option<Vec<u8>> {
+	if prev.strip_lengths() != next.strip_lengths() {
+		return None;
+	}
+
+	let total = next.as_bytes().len() / BYTES_PER_LED;
+	let mut data = vec![0; MESSAGE_NUM_LEN];
+	let mut changed = 0;
+
+	let pixels = prev
+		.as_bytes()
+		.chunks_exact(BYTES_PER_LED)
+		.zip(next.as_bytes().chunks_exact(BYTES_PER_LED));
+	for (idx, (prev, next)) in pixels.enumerate() {
+		if prev == next {
+			continue;
+		}
+
+		changed += 1;
+		if changed * 2 > total {
+			return None;
+		}
+
+		data.extend_from_slice(&u16::to_le_bytes(idx as u16));
+		data.extend_from_slice(next);
+	}
+
+	data[..MESSAGE_NUM_LEN].copy_from_slice(&u32::to_le_bytes(changed as u32));
+
+	Some(data)
+}
+
+/// Run-length encodes the strip-major `leds` into `out` as data for the rle update command.
+///
+/// Returns `false` once the encoding isn't smaller than `leds`, sending them as they are is the
+/// better option in that case.
+pub(crate) fn encode_rle(leds: &[u8], out: &mut Vec<u8>) -> bool {
+	out.clear();
+	out.extend_from_slice(&[0; MESSAGE_NUM_LEN]);
+	let mut runs = 0;
+
+	let mut pixels = leds.chunks_exact(BYTES_PER_LED).peekable();
+	while let Some(color) = pixels.next() {
+		let mut len = 1;
+		while len < u8::MAX && pixels.next_if_eq(&color).is_some() {
+			len += 1;
+		}
+
+		if out.len() + RLE_RUN_LEN >= leds.len() {
+			return false;
+		}
+
+		out.push(len);
+		out.extend_from_slice(color);
+		runs += 1;
+	}
+
+	out[..MESSAGE_NUM_LEN].copy_from_slice(&u32::to_le_bytes(runs));
+
+	true
+}
+
+/// The responses the device can send, used to name them in errors.
+const KNOWN_RESPONSES: [(&[u8; DEVICE_MESSAGE_TYPE_LEN], &str); 5] = [
+	(DEVICE_INIT_MESSAGE, "DEVICE_INIT"),
+	(DEVICE_ERROR_MESSAGE, "DEVICE_ERROR"),
+	(DEVICE_PARTIAL_MESSAGE, "DEVICE_PARTIAL"),
+	(DEVICE_OK_MESSAGE, "DEVICE_OK"),
+	(DEVICE_RESEND_MESSAGE, "DEVICE_RESEND"),
+];
+
+/// Formats a response for error messages, e.g. `e (0x65) = DEVICE_ERROR`.
+///
+/// Bytes that aren't printable are only shown as hex.
+pub(crate) fn describe_response(response: &[u8; DEVICE_MESSAGE_TYPE_LEN]) -> String {
+	let byte = response[0];
+	let mut description = if byte.is_ascii_graphic() {
+		format!("{} (0x{byte:02x})", byte as char)
+	} else {
+		format!("0x{byte:02x}")
+	};
+
+	if let Some((_, name)) = KNOWN_RESPONSES.iter().find(|(known, _)| *known == response) {
+		description.push_str(" = ");
+		description.push_str(name);
+	}
+
+	description
+}
+
+/// Checks a single response from the device against the expected message.
+pub(crate) fn check_response(
+	received: &[u8; DEVICE_MESSAGE_TYPE_LEN],
+	expected: &[u8; DEVICE_MESSAGE_TYPE_LEN],
+) -> Result<()> {
+	if received == DEVICE_RESEND_MESSAGE {
+		return Err(Error::ChecksumMismatch);
+	}
+
+	if received != expected {
+		return Err(Error::UnexpectedResponse {
+			expected: String::from_utf8_lossy(expected).to_string(),
+			received: describe_response(received),
+		});
+	}
+
+	Ok(())
+}
+
+/// Checks the response to a query, firmware that does not know the query responds with an error.
+pub(crate) fn check_query_response(received: &[u8; DEVICE_MESSAGE_TYPE_LEN]) -> Result<()> {
+	if received == DEVICE_ERROR_MESSAGE {
+		return Err(Error::UnsupportedCommand);
+	}
+
+	check_response(received, DEVICE_OK_MESSAGE)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+	u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+pub(crate) fn decode_capabilities(response: &[u8; CAPABILITIES_LEN]) -> Capabilities {
+	let mut values = response.chunks_exact(MESSAGE_NUM_LEN).map(read_u32);
+	let mut next = || values.next().unwrap_or_default();
+
+	Capabilities {
+		max_strips:         next() as usize,
+		max_leds_per_strip: next() as usize,
+		pixel_formats:      next(),
+		features:           next(),
+	}
+}
+
+/// The config from the response to [`GET_CONFIG_MESSAGE`](serial_ws2812_shared::GET_CONFIG_MESSAGE),
+/// with the host side settings left at their defaults.
+pub(crate) fn decode_config(response: &[u8; CONFIG_LEN]) -> Config {
+	let (strips, rest) = response.split_at(MESSAGE_NUM_LEN);
+	let (lengths, reversed) = rest.split_at(STRIP_LENGTHS_LEN);
+
+	let strips = (read_u32(strips) as usize).min(MAX_STRIPS);
+	let mut strip_lengths = [0; MAX_STRIPS];
+	for (len, bytes) in strip_lengths.iter_mut().zip(lengths.chunks_exact(2)) {
+		*len = u16::from_le_bytes([bytes[0], bytes[1]]);
+	}
+	let reversed = read_u32(reversed);
+
+	let leds = strip_lengths[..strips].iter().copied().max().unwrap_or(0) as usize;
+	let same_lengths = strip_lengths[..strips]
+		.iter()
+		.all(|&len| len as usize == leds);
+
+	Config {
+		strips,
+		leds,
+		strip_lengths: (!same_lengths).then_some(strip_lengths),
+		reversed: std::array::from_fn(|strip| reversed & 1 << strip != 0),
+		..Default::default()
+	}
+}
+
+pub(crate) fn decode_telemetry(response: &[u8; TELEMETRY_LEN]) -> Telemetry {
+	let temperature = read_u32(&response[..MESSAGE_NUM_LEN]) as i32;
+	let vsys = read_u32(&response[MESSAGE_NUM_LEN..]);
+
+	Telemetry {
+		temperature: temperature as f32 / 1000.0,
+		vsys:        vsys as f32 / 1000.0,
+	}
+}
+
+/// State for getting the device back to the start of a command.
+///
+/// The backends write [`RESYNC_MESSAGE`](serial_ws2812_shared::RESYNC_MESSAGE) first, which the
+/// device answers right away. Firmware that predates it doesn't answer, then null bytes are written
+/// until the device either rejects the command it is waiting for or completes it and starts over.
+pub(crate) struct Reset {
+	/// The device didn't answer the resync message, null bytes are written instead.
+	fallback:     bool,
+	counter:      usize,
+	/// Reads that timed out in total, to give up on devices that never answer.
+	timeouts:     usize,
+	max_timeouts: usize,
+}
+
+impl Default for Reset {
+	fn default() -> Self {
+		Self {
+			fallback:     false,
+			counter:      0,
+			timeouts:     0,
+			max_timeouts: Self::MAX_TIMEOUTS,
+		}
+	}
+}
+
+impl Reset {
+	/// Size of the buffer the backends should read into.
+	pub(crate) const BUFFER_LEN: usize = DEVICE_MESSAGE_TYPE_LEN * 4;
+	/// About 5 seconds with [`RESET_TIMEOUT`], enough null bytes to complete the largest command.
+	const MAX_TIMEOUTS: usize = 500;
+	/// About half a second with [`RESET_TIMEOUT`], see [`Self::probe`].
+	const PROBE_TIMEOUTS: usize = 50;
+
+	/// Gives up sooner, for checking whether the device answers at all. Only reliable for firmware
+	/// that answers the resync message, older firmware might need more null bytes.
+	pub(crate) fn probe() -> Self {
+		Self {
+			max_timeouts: Self::PROBE_TIMEOUTS,
+			..Self::default()
+		}
+	}
+
+	/// Called when a read timed out, returns the bytes to write to force a response or `None` if the
+	/// device didn't answer for too long.
+	pub(crate) fn on_timeout(&mut self) -> Option<&'static [u8]> {
+		self.timeouts += 1;
+		if self.timeouts > self.max_timeouts {
+			return None;
+		}
+
+		if !self.fallback {
+			info!("no response to resync, writing null bytes to force a response");
+			self.fallback = true;
+		}
+
+		self.counter += 1;
+		if self.counter < 8 {
+			Some(&[0u8])
+		} else {
+			Some(&[0u8; 32])
+		}
+	}
+
+	/// Called with the bytes read from the device, returns `true` once the device is waiting for a
+	/// command.
+	pub(crate) fn on_read(&mut self, received: &[u8]) -> bool {
+		// old firmware might reject parts of the resync message, only the response to it counts
+		if !self.fallback {
+			return received.ends_with(DEVICE_RESYNC_MESSAGE);
+		}
+
+		// if we receive more than one byte we're probably in the branch that writes 32 bytes and need to repeat the process
+		if received.len() > 1 {
+			self.counter = 0;
+			return false;
+		}
+
+		received == DEVICE_INIT_MESSAGE || received == DEVICE_ERROR_MESSAGE
+	}
+}
+
+/// The error for a device that never answered the reset, usually because the port belongs to
+/// another device.
+pub(crate) fn reset_failed(port: Option<String>) -> Error {
+	let port = port.unwrap_or_else(|| "the serial port".to_string());
+	warn!("{port} didn't respond to the reset, it might not be a serial ws2812 device");
+
+	Error::ResetFailed(port)
+}
+
+/// Keeps track of which frames the device acknowledges in streaming mode.
+pub(crate) struct Stream {
+	ack_interval: u32,
+	unacked:      u32,
+}
+
+impl Stream {
+	pub(crate) fn new(ack_interval: u32) -> Self {
+		Self {
+			ack_interval: ack_interval.max(1),
+			unacked:      0,
+		}
+	}
+
+	/// Registers a sent frame, returns `true` if the device acknowledges it.
+	pub(crate) fn frame_sent(&mut self) -> bool {
+		self.unacked += 1;
+		if self.unacked < self.ack_interval {
+			return false;
+		}
+
+		self.unacked = 0;
+		true
+	}
+}
+
+/// Reads of a single response from the device.
+///
+/// A read can return nothing during a momentary stall and the response on the next one, so those
+/// reads are repeated until the deadline of the backend. A closed port returns nothing right away
+/// every time, after [`Self::MAX_EMPTY_READS`] of them in a row it is reported instead of spinning
+/// until the deadline.
+#[derive(Default)]
+pub(crate) struct ResponseRead {
+	empty_reads: usize,
+}
+
+impl ResponseRead {
+	pub(crate) const MAX_EMPTY_READS: usize = 100;
+
+	/// Called with the result of a read, `Ok(true)` once the response arrived and `Ok(false)` if the
+	/// read has to be repeated.
+	pub(crate) fn on_read(&mut self, result: io::Result<usize>) -> Result<bool> {
+		match result {
+			Ok(DEVICE_MESSAGE_TYPE_LEN) => Ok(true),
+			Ok(_) => {
+				self.empty_reads += 1;
+				if self.empty_reads >= Self::MAX_EMPTY_READS {
+					return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+				}
+				Ok(false)
+			}
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(false),
+			Err(e) if e.kind() == io::ErrorKind::TimedOut => Err(Error::NoResponse),
+			Err(e) => Err(e.into()),
+		}
+	}
+}
+
+/// What a backend has to do next for an [`Exchange`].
+pub(crate) enum Step<'a> {
+	/// Write all of the bytes, writing less is [`Error::IncompleteWrite`].
+	Write(&'a [u8]),
+	/// Read a single response and pass it to [`Exchange::on_response`].
+	Read,
+	/// The device acknowledged the command.
+	Done(WriteResult),
+}
+
+#[derive(Clone, Copy)]
+enum ExchangeState {
+	Command,
+	Partial,
+	Data,
+	Ok,
+	Query,
+	Done,
+}
+
+/// A command on its way to the device, the backends only do the reads and writes it asks for.
+///
+/// The command is acknowledged with [`DEVICE_PARTIAL_MESSAGE`], unless the commands are framed and
+/// the length of the data follows it. The device acknowledges the data with [`DEVICE_OK_MESSAGE`]
+/// once all of it arrived. Queries have no data, the response is followed by the payload the
+/// backend reads on its own.
+pub(crate) struct Exchange<'a> {
+	/// The command followed by the length of the data.
+	header: [u8; MESSAGE_TYPE_LEN + MESSAGE_NUM_LEN],
+	data:   &'a [u8],
+	framed: bool,
+	query:  bool,
+	state:  ExchangeState,
+
+	command_start: Duration,
+	data_start:    Duration,
+}
+
+impl<'a> Exchange<'a> {
+	/// Sends `command` with `data`, `now` is the time of the clock of the controller.
+	pub(crate) fn command(
+		command: &[u8; MESSAGE_TYPE_LEN],
+		data: &'a [u8],
+		framed: bool,
+		now: Duration,
+	) -> Self {
+		Self::new(command, data, framed, now, false)
+	}
+
+	/// Sends the query `command`.
+	pub(crate) fn query(command: &[u8; MESSAGE_TYPE_LEN], framed: bool, now: Duration) -> Self {
+		Self::new(command, &[], framed, now, true)
+	}
+
+	fn new(
+		command: &[u8; MESSAGE_TYPE_LEN],
+		data: &'a [u8],
+		framed: bool,
+		now: Duration,
+		query: bool,
+	) -> Self {
+		let mut header = [0; MESSAGE_TYPE_LEN + MESSAGE_NUM_LEN];
+		header[..MESSAGE_TYPE_LEN].copy_from_slice(command);
+		header[MESSAGE_TYPE_LEN..].copy_from_slice(&(data.len() as u32).to_le_bytes());
+
+		trace!(
+			"sending {} command, {} bytes of data follow",
+			command_name(command),
+			data.len()
+		);
+
+		Self {
+			header,
+			data,
+			framed,
+			query,
+			state: ExchangeState::Command,
+
+			command_start: now,
+			data_start: now,
+		}
+	}
+
+	/// The next step, `now` is the time of the clock of the controller.
+	pub(crate) fn next(&mut self, now: Duration) -> Step<'_> {
+		match self.state {
+			ExchangeState::Command => {
+				self.state = match (self.query, self.framed) {
+					(true, _) => ExchangeState::Query,
+					(false, true) => ExchangeState::Data,
+					(false, false) => ExchangeState::Partial,
+				};
+
+				let len = if self.framed {
+					self.header.len()
+				} else {
+					MESSAGE_TYPE_LEN
+				};
+				Step::Write(&self.header[..len])
+			}
+			ExchangeState::Partial | ExchangeState::Ok | ExchangeState::Query => Step::Read,
+			ExchangeState::Data => {
+				self.data_start = now;
+				self.state = ExchangeState::Ok;
+				trace!("sending {} bytes of data", self.data.len());
+				Step::Write(self.data)
+			}
+			ExchangeState::Done => {
+				log::record_timings(self.data_start - self.command_start, now - self.data_start);
+
+				#[cfg(feature = "timings")]
+				return Step::Done(crate::Timings {
+					command: self.data_start - self.command_start,
+					data:    now - self.data_start,
+				});
+
+				#[cfg(not(feature = "timings"))]
+				Step::Done(())
+			}
+		}
+	}
+
+	/// Checks the response the backend read for [`Step::Read`].
+	pub(crate) fn on_response(&mut self, response: &[u8; DEVICE_MESSAGE_TYPE_LEN]) -> Result<()> {
+		self.state = match self.state {
+			ExchangeState::Partial => {
+				check_response(response, DEVICE_PARTIAL_MESSAGE)?;
+				ExchangeState::Data
+			}
+			ExchangeState::Ok => {
+				check_response(response, DEVICE_OK_MESSAGE)?;
+				ExchangeState::Done
+			}
+			ExchangeState::Query => {
+				check_query_response(response)?;
+				ExchangeState::Done
+			}
+			state => state,
+		};
+
+		Ok(())
+	}
+}
+
+/// The commands that send a frame, they are counted in
+/// [`ControllerStats::frames_sent`](crate::ControllerStats::frames_sent).
+const FRAME_COMMANDS: [&[u8; MESSAGE_TYPE_LEN]; 4] = [
+	UPDATE_MESSAGE,
+	UPDATE_CHECKED_MESSAGE,
+	RLE_UPDATE_MESSAGE,
+	DIFF_MESSAGE,
+];
+
+pub(crate) fn is_frame_command(command: &[u8; MESSAGE_TYPE_LEN]) -> bool {
+	FRAME_COMMANDS.contains(&command)
+}
+
+/// Picks the command the strip-major `leds` are sent with, `encoded` is reused for the data if it
+/// has to be changed.
+///
+/// Checksums take precedence over run-length encoding, which is only used if it is smaller.
+pub(crate) fn update_command<'a>(
+	config: &Config,
+	checksums: bool,
+	rle: bool,
+	leds: &'a [u8],
+	encoded: &'a mut Vec<u8>,
+) -> (&'static [u8; MESSAGE_TYPE_LEN], &'a [u8]) {
+	if checksums {
+		encoded.clear();
+		encoded.extend_from_slice(leds);
+		encoded.extend_from_slice(&crc32(leds).to_le_bytes());
+		(UPDATE_CHECKED_MESSAGE, encoded)
+	} else if rle
+		// the packed RGBW data doesn't line up with the colors of the runs
+		&& config.rgbw_mode.is_none()
+		&& encode_rle(leds, encoded)
+	{
+		(RLE_UPDATE_MESSAGE, encoded)
+	} else {
+		(UPDATE_MESSAGE, leds)
+	}
+}
+
+/// How the backends recover after sending a frame failed, see [`Retry`].
+pub(crate) enum Recovery {
+	/// The device is waiting for the next command, the frame is sent again right away.
+	Resend,
+	/// The device is reset to the start of a command before the frame is sent again.
+	Reset,
+}
+
+/// Decides whether a frame is sent again after an error.
+///
+/// Corrupted frames are resent up to [`MAX_RESENDS`] times, failures that leave the device in an
+/// unknown state are retried after a reset as often as the frame retries of the controller allow.
+pub(crate) struct Retry {
+	retries: usize,
+	resends: usize,
+}
+
+impl Retry {
+	pub(crate) fn new(retries: usize) -> Self {
+		Self {
+			retries,
+			resends: MAX_RESENDS,
+		}
+	}
+
+	/// Called when sending the frame failed with `error`, `None` if it is returned to the caller.
+	pub(crate) fn on_error(&mut self, error: &Error) -> Option<Recovery> {
+		match error {
+			Error::ChecksumMismatch if self.resends > 0 => {
+				warn!("device received a corrupted frame, resending");
+				self.resends -= 1;
+				Some(Recovery::Resend)
+			}
+			Error::NoResponse | Error::IncompleteWrite | Error::UnexpectedResponse { .. }
+				if self.retries > 0 =>
+			{
+				warn!("sending frame failed, retrying: {error}");
+				self.retries -= 1;
+				Some(Recovery::Reset)
+			}
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Three strips with 3, 1 and 2 LEDs.
+	fn unequal_config() -> Config {
+		Config::strips(3, 3).with_strip_lengths([3, 1, 2, 0, 0, 0, 0, 0])
+	}
+
+	/// The bytes of LEDs that are all different, with the value of the LED in every channel.
+	fn leds(values: &[u8]) -> Vec<u8> {
+		values
+			.iter()
+			.flat_map(|&value| [value; BYTES_PER_LED])
+			.collect()
+	}
+
+	#[test]
+	fn flat_skips_the_padding_of_shorter_strips() {
+		let pixels: Vec<RGB> = (0..9).map(|i| RGB::new(i, i, i)).collect();
+
+		let leds = flat_to_strip_major(&unequal_config(), &pixels).unwrap();
+		assert_eq!(leds, self::leds(&[0, 1, 2, 3, 6, 7]));
+	}
+
+	#[test]
+	fn flat_rejects_other_sizes() {
+		let pixels = vec![RGB::default(); 6];
+
+		assert!(matches!(
+			flat_to_strip_major(&unequal_config(), &pixels),
+			Err(Error::BufferSizeMismatch {
+				expected: 27,
+				received: 18,
+			})
+		));
+	}
+
+	#[test]
+	fn led_major_with_unequal_strips() {
+		// LED 0 of all three strips, LED 1 of strips 0 and 2, LED 2 of strip 0
+		let order: Vec<usize> =
+			strip_major_order(&unequal_config(), BufferLayout::LedMajor).collect();
+		assert_eq!(order, [0, 3, 5, 1, 2, 4]);
+
+		let order: Vec<usize> =
+			strip_major_order(&unequal_config(), BufferLayout::StripMajor).collect();
+		assert_eq!(order, [0, 1, 2, 3, 4, 5]);
+	}
+
+	#[test]
+	fn reversed_strips_are_flipped_by_the_device() {
+		let config =
+			unequal_config().with_reversed([true, false, true, false, false, false, false, false]);
+
+		for layout in [BufferLayout::StripMajor, BufferLayout::LedMajor] {
+			assert!(
+				strip_major_order(&config, layout).eq(strip_major_order(&unequal_config(), layout))
+			);
+		}
+	}
+
+	#[test]
+	fn encode_leds_round_trip() {
+		let strip_major = leds(&[10, 11, 12, 20, 30, 31]);
+		let led_major = leds(&[10, 20, 30, 11, 31, 12]);
+		let mut out = Vec::new();
+
+		for config in [
+			unequal_config(),
+			unequal_config().with_reversed([true; MAX_STRIPS]),
+		] {
+			encode_leds(
+				&config,
+				BufferLayout::LedMajor,
+				NO_CHANNEL_SCALE,
+				&led_major,
+				&mut out,
+			);
+			assert_eq!(out, strip_major);
+
+			encode_leds(
+				&config,
+				BufferLayout::StripMajor,
+				NO_CHANNEL_SCALE,
+				&strip_major,
+				&mut out,
+			);
+			assert_eq!(out, strip_major);
+		}
+	}
+
+	/// Drives `exchange` with the `responses` of the device, returns what was written.
+	fn drive(mut exchange: Exchange<'_>, responses: &[u8]) -> (Vec<Vec<u8>>, Result<()>) {
+		let mut written = Vec::new();
+		let mut responses = responses.iter();
+		loop {
+			match exchange.next(Duration::ZERO) {
+				Step::Write(bytes) => written.push(bytes.to_vec()),
+				Step::Read => {
+					let response = [*responses.next().expect("no response left")];
+					if let Err(e) = exchange.on_response(&response) {
+						return (written, Err(e));
+					}
+				}
+				Step::Done(_) => return (written, Ok(())),
+			}
+		}
+	}
+
+	#[test]
+	fn command_waits_for_the_device_before_the_data() {
+		let exchange = Exchange::command(SET_LEDS_MESSAGE, &[1, 0, 0, 0], false, Duration::ZERO);
+
+		let (written, result) = drive(exchange, b"pk");
+		result.unwrap();
+		assert_eq!(written, [SET_LEDS_MESSAGE.to_vec(), vec![1, 0, 0, 0]]);
+	}
+
+	#[test]
+	fn framed_command_sends_the_length() {
+		let exchange = Exchange::command(SET_LEDS_MESSAGE, &[1, 0, 0, 0], true, Duration::ZERO);
+
+		let (written, result) = drive(exchange, b"k");
+		result.unwrap();
+
+		let mut header = SET_LEDS_MESSAGE.to_vec();
+		header.extend_from_slice(&[4, 0, 0, 0]);
+		assert_eq!(written, [header, vec![1, 0, 0, 0]]);
+	}
+
+	#[test]
+	fn rejected_command_stops_before_the_data() {
+		let exchange = Exchange::command(SET_LEDS_MESSAGE, &[1, 0, 0, 0], false, Duration::ZERO);
+
+		let (written, result) = drive(exchange, b"e");
+		assert!(matches!(result, Err(Error::UnexpectedResponse { .. })));
+		assert_eq!(written, [SET_LEDS_MESSAGE.to_vec()]);
+	}
+
+	#[test]
+	fn unknown_query_is_unsupported() {
+		let exchange = Exchange::query(SET_STRIPS_MESSAGE, false, Duration::ZERO);
+
+		let (written, result) = drive(exchange, b"e");
+		assert!(matches!(result, Err(Error::UnsupportedCommand)));
+		assert_eq!(written, [SET_STRIPS_MESSAGE.to_vec()]);
+	}
+
+	#[test]
+	fn corrupted_frames_are_resent_once() {
+		let mut retry = Retry::new(0);
+
+		assert!(matches!(
+			retry.on_error(&Error::ChecksumMismatch),
+			Some(Recovery::Resend)
+		));
+		assert!(retry.on_error(&Error::ChecksumMismatch).is_none());
+	}
+
+	#[test]
+	fn failed_frames_are_retried_after_a_reset() {
+		let mut retry = Retry::new(1);
+
+		assert!(matches!(
+			retry.on_error(&Error::NoResponse),
+			Some(Recovery::Reset)
+		));
+		assert!(retry.on_error(&Error::NoResponse).is_none());
+		assert!(Retry::new(1).on_error(&Error::Streaming).is_none());
+	}
+
+	#[test]
+	fn update_command_prefers_checksums() {
+		let config = Config::strips(1, 4);
+		let leds = [0; 12];
+		let mut encoded = Vec::new();
+
+		let (command, data) = update_command(&config, true, true, &leds, &mut encoded);
+		assert_eq!(command, UPDATE_CHECKED_MESSAGE);
+		assert_eq!(data.len(), leds.len() + 4);
+
+		let (command, _) = update_command(&config, false, true, &leds, &mut encoded);
+		assert_eq!(command, RLE_UPDATE_MESSAGE);
+
+		let (command, data) = update_command(&config, false, false, &leds, &mut encoded);
+		assert_eq!(command, UPDATE_MESSAGE);
+		assert_eq!(data, leds);
+	}
+}
@@ -0,0 +1,100 @@
+//! Driving RGBW strips (SK6812 RGBW) with the RGB firmware.
+//!
+//! The firmware doesn't know about the white channel, it only shifts out 3 bytes per LED. An RGBW
+//! strip with `n` LEDs is therefore configured on the device as a strip with `ceil(4n / 3)` LEDs and
+//! the 4 bytes per LED are packed into those, the few bits left over at the end of the strip go to
+//! an LED that doesn't exist.
+
+use crate::{Config, BYTES_PER_LED};
+
+/// The amount of bytes per LED on an RGBW strip.
+pub const BYTES_PER_RGBW_LED: usize = 4;
+
+/// The color of the white LED in RGB, a typical neutral white (about 4500K) SK6812.
+const WHITE_POINT: [u8; 3] = [255, 224, 176];
+
+/// How the white channel of RGBW strips is computed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RgbwConversion {
+	/// No conversion, the buffer already contains 4 bytes (red, green, blue, white) per LED.
+	None,
+	/// The smallest of the RGB channels moves to the white channel and is subtracted from the
+	/// others, like FastLED does it.
+	///
+	/// Uses the white LED as much as possible, which is the brightest and most efficient option, but
+	/// the white LED is warmer than white mixed from RGB so unsaturated colors shift slightly
+	/// towards its tint.
+	MaxSubtract,
+	/// Like [`Self::MaxSubtract`], but the tint of the white LED is compensated by only moving as
+	/// much to white as can be reproduced by it and leaving the rest on the RGB channels.
+	///
+	/// Colors stay closer to the RGB input, at the cost of using a bit less of the white LED. Assumes
+	/// a neutral white LED, cold white strips end up slightly blue.
+	Accurate,
+}
+
+impl RgbwConversion {
+	/// The amount of bytes per LED in the buffers passed to the controller.
+	pub fn bytes_per_led(self) -> usize {
+		match self {
+			RgbwConversion::None => BYTES_PER_RGBW_LED,
+			RgbwConversion::MaxSubtract | RgbwConversion::Accurate => BYTES_PER_LED,
+		}
+	}
+
+	/// Converts a single color to red, green, blue and white.
+	fn convert(self, color: &[u8]) -> [u8; BYTES_PER_RGBW_LED] {
+		let [r, g, b] = [color[0], color[1], color[2]];
+
+		match self {
+			RgbwConversion::None => [r, g, b, color[3]],
+			RgbwConversion::MaxSubtract => {
+				let w = r.min(g).min(b);
+				[r - w, g - w, b - w, w]
+			}
+			RgbwConversion::Accurate => {
+				let w = [r, g, b]
+					.into_iter()
+					.zip(WHITE_POINT)
+					.map(|(channel, white)| channel as u32 * 255 / white as u32)
+					.min()
+					.unwrap_or(0)
+					.min(255);
+
+				// never more than the channel itself, as `w` is the minimum of all channels
+				let subtract = WHITE_POINT.map(|white| (w * white as u32 / 255) as u8);
+
+				[r - subtract[0], g - subtract[1], b - subtract[2], w as u8]
+			}
+		}
+	}
+}
+
+/// The amount of LEDs the device has to be configured with for an RGBW strip.
+pub(crate) fn device_strip_len(leds: usize) -> usize {
+	(leds * BYTES_PER_RGBW_LED).div_ceil(BYTES_PER_LED)
+}
+
+/// Appends the data the device expects for the colors, as red, green, blue and white, to `out`.
+pub(crate) fn encode_into(
+	config: &Config,
+	conversion: RgbwConversion,
+	mut colors: impl Iterator<Item = [u8; BYTES_PER_RGBW_LED]>,
+	out: &mut Vec<u8>,
+) {
+	for len in config.strip_lens() {
+		let start = out.len();
+		for color in colors.by_ref().take(len) {
+			// the device sends green first, the same as it does for RGB
+			let [r, g, b, w] = conversion.convert(&color);
+			out.extend_from_slice(&[g, r, b, w]);
+		}
+		out.resize(start + device_strip_len(len) * BYTES_PER_LED, 0);
+
+		// the device swaps the first 2 bytes of every LED (RGB to GRB), which is undone here so the
+		// bytes come out in order
+		for led in out[start..].chunks_exact_mut(BYTES_PER_LED) {
+			led.swap(0, 1);
+		}
+	}
+}
@@ -0,0 +1,192 @@
+//! Receives sACN (E1.31) and forwards it to the device.
+//!
+//! Only what is needed to receive DMX data is implemented: data packets for the mapped universes
+//! are applied to a frame, everything else (sync, discovery, other universes) is ignored.
+
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+use crate::{
+	log::{debug, info},
+	Frame,
+	Result,
+	SerialWs2812,
+	BYTES_PER_LED,
+};
+
+/// The UDP port sACN is sent to.
+pub const SACN_PORT: u16 = 5568;
+/// The amount of LEDs that fit into the 512 channels of a universe.
+pub const LEDS_PER_UNIVERSE: usize = 512 / BYTES_PER_LED;
+
+const ACN_PACKET_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: [u8; 4] = [0, 0, 0, 4];
+const VECTOR_E131_DATA_PACKET: [u8; 4] = [0, 0, 0, 2];
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+const OPTION_PREVIEW_DATA: u8 = 1 << 7;
+const DMX_START_CODE: u8 = 0;
+/// Offset of the start code, the DMX data directly follows it.
+const DMX_START: usize = 125;
+
+/// Maps a strip onto consecutive universes.
+///
+/// The first [`LEDS_PER_UNIVERSE`] LEDs of the strip are in `universe`, the next ones in the
+/// universe after it and so on, the last 2 channels of every universe are unused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StripUniverse {
+	pub strip:    usize,
+	pub universe: u16,
+}
+
+/// Listens for sACN and sends the received data to the device.
+pub struct SacnBridge {
+	controller: SerialWs2812,
+	socket:     UdpSocket,
+	mapping:    Vec<StripUniverse>,
+	universes:  Vec<u16>,
+	frame:      Frame,
+	/// Universes that were received since the last frame was sent.
+	received:   Vec<u16>,
+}
+
+impl SacnBridge {
+	/// Binds to the sACN port on all interfaces and joins the multicast groups of the mapped
+	/// universes.
+	pub fn new(controller: SerialWs2812, mapping: Vec<StripUniverse>) -> Result<Self> {
+		let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, SACN_PORT)))?;
+		let frame = Frame::new(controller.config());
+
+		let mut universes: Vec<u16> = mapping
+			.iter()
+			.filter(|mapping| mapping.strip < frame.strips())
+			.flat_map(|mapping| {
+				let count = frame.strip_lengths()[mapping.strip].div_ceil(LEDS_PER_UNIVERSE);
+				(0..count as u16).map(move |offset| mapping.universe.saturating_add(offset))
+			})
+			.collect();
+		universes.sort_unstable();
+		universes.dedup();
+
+		for &universe in &universes {
+			socket.join_multicast_v4(&multicast_address(universe), &Ipv4Addr::UNSPECIFIED)?;
+		}
+		info!("listening for sACN on universes {universes:?}");
+
+		Ok(Self {
+			controller,
+			socket,
+			mapping,
+			universes,
+			frame,
+			received: Vec::new(),
+		})
+	}
+
+	/// All universes the configured strips span.
+	pub fn universes(&self) -> &[u16] {
+		&self.universes
+	}
+
+	/// Receives and forwards packets until an error occurs.
+	pub fn run(&mut self) -> Result<()> {
+		loop {
+			self.poll()?;
+		}
+	}
+
+	/// Receives a single packet and applies it.
+	///
+	/// A frame is sent once every mapped universe was received, or when a universe is received a
+	/// second time before that so a missing universe doesn't stall the output.
+	pub fn poll(&mut self) -> Result<()> {
+		let mut packet = [0u8; 638];
+		let len = self.socket.recv(&mut packet)?;
+
+		let Some((universe, data)) = parse_packet(&packet[..len]) else {
+			return Ok(());
+		};
+
+		if !self.universes.contains(&universe) {
+			return Ok(());
+		}
+
+		if self.received.contains(&universe) {
+			debug!("universe {universe} received twice, sending incomplete frame");
+			self.send()?;
+		}
+
+		self.apply(universe, data);
+		self.received.push(universe);
+
+		if self
+			.universes
+			.iter()
+			.all(|universe| self.received.contains(universe))
+		{
+			self.send()?;
+		}
+
+		Ok(())
+	}
+
+	/// Gives back the controller.
+	pub fn into_inner(self) -> SerialWs2812 {
+		self.controller
+	}
+
+	fn apply(&mut self, universe: u16, data: &[u8]) {
+		for mapping in &self.mapping {
+			let Some(part) = universe.checked_sub(mapping.universe) else {
+				continue;
+			};
+			if mapping.strip >= self.frame.strips() {
+				continue;
+			}
+
+			let len = self.frame.strip_lengths()[mapping.strip];
+			let start = part as usize * LEDS_PER_UNIVERSE;
+			let end = len.min(start + LEDS_PER_UNIVERSE);
+
+			for (led, color) in (start..end).zip(data.chunks_exact(BYTES_PER_LED)) {
+				self.frame
+					.set_pixel(mapping.strip, led, [color[0], color[1], color[2]]);
+			}
+		}
+	}
+
+	fn send(&mut self) -> Result<()> {
+		self.received.clear();
+		self.controller.send_frame(&self.frame)?;
+
+		Ok(())
+	}
+}
+
+/// The multicast group a universe is sent to.
+fn multicast_address(universe: u16) -> Ipv4Addr {
+	let [hi, lo] = universe.to_be_bytes();
+	Ipv4Addr::new(239, 255, hi, lo)
+}
+
+/// Returns the universe and DMX data of an E1.31 data packet.
+fn parse_packet(packet: &[u8]) -> Option<(u16, &[u8])> {
+	if packet.len() <= DMX_START
+		|| &packet[4..16] != ACN_PACKET_IDENTIFIER
+		|| packet[18..22] != VECTOR_ROOT_E131_DATA
+		|| packet[40..44] != VECTOR_E131_DATA_PACKET
+		|| packet[117] != VECTOR_DMP_SET_PROPERTY
+		|| packet[DMX_START] != DMX_START_CODE
+	{
+		return None;
+	}
+
+	if packet[112] & OPTION_PREVIEW_DATA != 0 {
+		return None;
+	}
+
+	let universe = u16::from_be_bytes([packet[113], packet[114]]);
+	// the count includes the start code
+	let count = u16::from_be_bytes([packet[123], packet[124]]) as usize;
+	let end = packet.len().min(DMX_START + count.max(1));
+
+	Some((universe, &packet[DMX_START + 1..end]))
+}
@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use serial_ws2812_shared::DEVICE_ID_LEN;
+
+use crate::{Capabilities, Config, Telemetry, RGB};
+
+/// The colors every LED is set to in turn, to spot dead channels.
+pub(crate) const TEST_COLORS: [RGB; 4] = [
+	RGB::new(255, 0, 0),
+	RGB::new(0, 255, 0),
+	RGB::new(0, 0, 255),
+	RGB::new(255, 255, 255),
+];
+
+/// Every test color and whether the device acknowledged the frame.
+pub(crate) type ColorResults = [(RGB, bool); TEST_COLORS.len()];
+
+/// How long every color is shown.
+pub(crate) const COLOR_DURATION: Duration = Duration::from_millis(500);
+
+/// The amount of frames the frame rate is measured over.
+pub(crate) const FPS_FRAMES: u32 = 30;
+
+/// What [`crate::SerialWs2812::self_test`] found out about the device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelfTestReport {
+	pub capabilities: Capabilities,
+	/// `None` if the firmware doesn't support [`crate::FEATURE_DEVICE_ID`].
+	pub device_id:    Option<[u8; DEVICE_ID_LEN]>,
+	/// `None` if the firmware doesn't support [`crate::FEATURE_TELEMETRY`].
+	pub telemetry:    Option<Telemetry>,
+	/// The round trip of a ping, `None` if the firmware doesn't support [`crate::FEATURE_PING`].
+	pub round_trip:   Option<Duration>,
+	/// Every test color and whether the device acknowledged the frame.
+	pub colors:       ColorResults,
+	/// The frames per second the device took for the current config, `None` if sending failed.
+	pub fps:          Option<f32>,
+}
+
+impl SelfTestReport {
+	/// Whether every frame was acknowledged.
+	pub fn passed(&self) -> bool {
+		self.colors.iter().all(|&(_, acked)| acked) && self.fps.is_some()
+	}
+}
+
+/// A frame with every LED set to `color`, in the format [`Config::bytes_per_led`] asks for.
+pub(crate) fn fill(config: &Config, color: RGB) -> Vec<u8> {
+	let mut led = vec![0; config.bytes_per_led()];
+	led[..3].copy_from_slice(&color.to_array());
+
+	led.repeat(config.leds_total())
+}
@@ -0,0 +1,209 @@
+//! Shows the frames in the terminal instead of sending them to a device, to work on animations
+//! without the hardware.
+//!
+//! The LEDs are drawn with 24 bit color escape codes, which most terminals support. Every frame
+//! replaces the previous one in place.
+
+use std::io::{self, Write};
+
+use serial_ws2812_shared::{MAX_LEDS_PER_STRIP, MAX_STRIPS, PIXEL_FORMAT_RGB};
+
+use crate::{
+	protocol::to_strip_major,
+	AsyncWs2812Controller,
+	Capabilities,
+	Config,
+	Error,
+	Layout,
+	Result,
+	WriteResult,
+	Ws2812Controller,
+	BYTES_PER_LED,
+};
+
+/// What the simulated device supports, the limits of the firmware with every feature.
+const CAPABILITIES: Capabilities = Capabilities {
+	max_strips:         MAX_STRIPS,
+	max_leds_per_strip: MAX_LEDS_PER_STRIP,
+	pixel_formats:      PIXEL_FORMAT_RGB,
+	features:           u32::MAX,
+};
+
+/// Takes the same LED data as [`crate::SerialWs2812`] and draws it in the terminal.
+pub struct SimulatorWs2812 {
+	config: Config,
+	layout: Option<Layout>,
+	output: Box<dyn Write + Send>,
+
+	/// The last frame, as passed to [`Self::send_leds`].
+	leds:       Vec<u8>,
+	/// The amount of lines the last frame took up, to draw the next one over it.
+	lines_down: usize,
+}
+
+impl SimulatorWs2812 {
+	/// Create a simulator that draws to stdout.
+	pub fn new(config: Config) -> Self {
+		Self::with_output(config, Box::new(io::stdout()))
+	}
+
+	/// Create a simulator that draws to `output`.
+	pub fn with_output(config: Config, output: Box<dyn Write + Send>) -> Self {
+		Self {
+			config,
+			layout: None,
+			output,
+
+			leds: Vec::new(),
+			lines_down: 0,
+		}
+	}
+
+	/// Draws the LEDs as a matrix panel instead of one line per strip.
+	pub fn set_layout(&mut self, layout: Option<Layout>) {
+		self.layout = layout;
+	}
+
+	/// The current configuration of the instance.
+	pub fn config(&self) -> &Config {
+		&self.config
+	}
+
+	/// Sets the configuration for the instance.
+	pub fn set_config(&mut self, config: Config) -> Result<()> {
+		self.config = config;
+		self.configure()
+	}
+
+	/// Checks the config like the device would.
+	pub fn configure(&mut self) -> Result<()> {
+		self.config.validate(&CAPABILITIES)
+	}
+
+	/// The amount of bytes [`Self::send_leds`] expects for the current config.
+	pub fn transfer_size(&self) -> usize {
+		self.config.buffer_size()
+	}
+
+	/// The LEDs of the last frame, in the strip-major order of the device.
+	pub fn leds(&self) -> &[u8] {
+		&self.leds
+	}
+
+	/// Draws the LEDs, the length must be the same as for [`crate::SerialWs2812::send_leds`].
+	pub fn send_leds(&mut self, leds: &[u8]) -> Result<WriteResult> {
+		let expected = self.transfer_size();
+		if leds.len() != expected {
+			return Err(Error::BufferSizeMismatch {
+				expected,
+				received: leds.len(),
+			});
+		}
+
+		self.leds.clear();
+		self.leds.extend(to_strip_major(
+			&self.config,
+			self.config.buffer_layout,
+			leds,
+		));
+		self.draw()?;
+
+		// nothing is sent, so there is nothing to time
+		#[cfg(feature = "timings")]
+		return Ok(WriteResult::default());
+
+		#[cfg(not(feature = "timings"))]
+		Ok(())
+	}
+
+	fn draw(&mut self) -> Result<()> {
+		let bytes_per_led = self.config.bytes_per_led();
+		let color = |idx: usize| -> [u8; BYTES_PER_LED] {
+			let Some(led) = self
+				.leds
+				.get(idx * bytes_per_led..(idx + 1) * bytes_per_led)
+			else {
+				return [0; BYTES_PER_LED];
+			};
+
+			// the white channel of RGBW strips is mixed into the others
+			let white = led.get(BYTES_PER_LED).copied().unwrap_or(0);
+			[led[0], led[1], led[2]].map(|channel| channel.saturating_add(white))
+		};
+
+		let lines: Vec<Vec<[u8; BYTES_PER_LED]>> = match self.layout {
+			Some(layout) => (0..layout.height)
+				.map(|y| {
+					(0..layout.width)
+						.map(|x| color(layout.index(x, y)))
+						.collect()
+				})
+				.collect(),
+			None => {
+				let mut start = 0;
+				self.config
+					.strip_lens()
+					.map(|len| {
+						let line = (start..start + len).map(color).collect();
+						start += len;
+						line
+					})
+					.collect()
+			}
+		};
+
+		let mut out = String::new();
+		if self.lines_down > 0 {
+			out.push_str(&format!("\x1b[{}F", self.lines_down));
+		}
+		for line in &lines {
+			for [r, g, b] in line {
+				out.push_str(&format!("\x1b[38;2;{r};{g};{b}m██"));
+			}
+			out.push_str("\x1b[0m\x1b[K\n");
+		}
+		self.lines_down = lines.len();
+
+		self.output.write_all(out.as_bytes())?;
+		self.output.flush()?;
+
+		Ok(())
+	}
+}
+
+impl Ws2812Controller for SimulatorWs2812 {
+	fn config(&self) -> &Config {
+		self.config()
+	}
+
+	fn set_config(&mut self, config: Config) -> Result<()> {
+		self.set_config(config)
+	}
+
+	fn configure(&mut self) -> Result<()> {
+		self.configure()
+	}
+
+	fn send_leds(&mut self, leds: &[u8]) -> Result<WriteResult> {
+		self.send_leds(leds)
+	}
+}
+
+/// Draws right away, the simulator never waits on anything.
+impl AsyncWs2812Controller for SimulatorWs2812 {
+	fn config(&self) -> &Config {
+		self.config()
+	}
+
+	async fn set_config(&mut self, config: Config) -> Result<()> {
+		self.set_config(config)
+	}
+
+	async fn configure(&mut self) -> Result<()> {
+		self.configure()
+	}
+
+	async fn send_leds(&mut self, leds: &[u8]) -> Result<WriteResult> {
+		self.send_leds(leds)
+	}
+}
@@ -0,0 +1,78 @@
+use std::{
+	sync::OnceLock,
+	time::{Duration, Instant},
+};
+
+/// Counters of what a controller sent since it was created or the stats were reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ControllerStats {
+	/// Frames the device acknowledged, streamed frames are counted once they are written.
+	pub frames_sent:  u64,
+	/// Bytes written to the serial port, including commands.
+	pub bytes_sent:   u64,
+	/// Writes to the serial port that failed.
+	pub write_errors: u64,
+	pub reconnects:   u64,
+	/// Frames sent again because the device received them corrupted, see
+	/// [`crate::SerialWs2812::set_update_checksums`].
+	pub resends:      u64,
+}
+
+/// How long the parts of a command took, returned with the `timings` feature.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Timings {
+	/// Writing the command until the device was ready for the data, this is where the device waits
+	/// for the LED task if it is still busy with the previous frame.
+	pub command: Duration,
+	/// Writing the data until the device acknowledged it.
+	pub data:    Duration,
+}
+
+/// A monotonic time since any fixed point, the [`Timings`] are measured with it.
+pub type Clock = Box<dyn Fn() -> Duration + Send>;
+
+/// The clock used unless another one is set, based on [`Instant`].
+pub(crate) fn default_clock() -> Clock {
+	static START: OnceLock<Instant> = OnceLock::new();
+
+	Box::new(|| START.get_or_init(Instant::now).elapsed())
+}
+
+/// How fast frames got to the device, see [`crate::SerialWs2812::measure_throughput`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ThroughputReport {
+	pub frames:        usize,
+	/// The fastest frame, from sending it until the device acknowledged it.
+	pub min:           Duration,
+	pub avg:           Duration,
+	pub max:           Duration,
+	/// The LED data that got through per second, without the commands.
+	pub bytes_per_sec: f64,
+}
+
+impl ThroughputReport {
+	/// Builds the report from the time every frame took, with `frame_bytes` of LED data each.
+	pub(crate) fn new(frame_times: &[Duration], frame_bytes: usize) -> Self {
+		let total: Duration = frame_times.iter().sum();
+		let frames = frame_times.len();
+
+		Self {
+			frames,
+			min: frame_times.iter().copied().min().unwrap_or_default(),
+			avg: total / frames.max(1) as u32,
+			max: frame_times.iter().copied().max().unwrap_or_default(),
+			bytes_per_sec: match total.is_zero() {
+				true => 0.0,
+				false => (frames * frame_bytes) as f64 / total.as_secs_f64(),
+			},
+		}
+	}
+
+	/// The frames per second the average frame time allows.
+	pub fn fps(&self) -> f64 {
+		match self.avg.is_zero() {
+			true => 0.0,
+			false => 1.0 / self.avg.as_secs_f64(),
+		}
+	}
+}
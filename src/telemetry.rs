@@ -0,0 +1,8 @@
+/// Health information reported by the device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Telemetry {
+	/// Temperature of the RP2040 in degrees celsius.
+	pub temperature: f32,
+	/// Supply voltage (VSYS) in volts, only meaningful on boards that wire it to GPIO29 like the pico.
+	pub vsys:        f32,
+}
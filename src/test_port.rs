@@ -0,0 +1,360 @@
+//! A serial port for tests that answers like the firmware, without any actual I/O.
+
+use std::{
+	collections::VecDeque,
+	io::{self, Read, Write},
+	sync::{Arc, Mutex, MutexGuard},
+	time::Duration,
+};
+#[cfg(feature = "tokio")]
+use std::{
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use serial_ws2812_shared::{
+	parse_command,
+	parse_data,
+	Command,
+	DataStep,
+	ParseStep,
+	ResyncDetector,
+	DEVICE_ERROR_MESSAGE,
+	DEVICE_INIT_MESSAGE,
+	DEVICE_OK_MESSAGE,
+	DEVICE_PARTIAL_MESSAGE,
+	DEVICE_RESYNC_MESSAGE,
+	MESSAGE_TYPE_LEN,
+};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The size of the USB packets of the device, every write takes at most one.
+const PACKET_LEN: usize = 64;
+
+/// Emulates the firmware, queries are rejected so the controller falls back to the legacy
+/// capabilities.
+///
+/// Clones share the device, so a test can inspect it after handing a clone to a controller.
+#[derive(Clone, Default)]
+pub(crate) struct FakePort {
+	device: Arc<Mutex<FakeDevice>>,
+}
+
+#[derive(Default)]
+struct FakeDevice {
+	responses: VecDeque<u8>,
+	received:  Vec<u8>,
+	command:   Option<Command>,
+	resync:    ResyncDetector,
+
+	strips: usize,
+	leds:   usize,
+
+	/// Everything the host wrote.
+	written:     Vec<u8>,
+	/// The writes it took.
+	packets:     usize,
+	/// The data of every update the device acknowledged.
+	frames:      Vec<Vec<u8>>,
+	/// Never responds, like a device that hangs.
+	silent:      bool,
+	/// The next reads return no bytes.
+	empty_reads: usize,
+}
+
+impl FakePort {
+	pub(crate) fn new() -> Self {
+		let port = Self::default();
+		port.device().responses.extend(DEVICE_INIT_MESSAGE);
+		port
+	}
+
+	/// A port that takes all writes but never responds.
+	pub(crate) fn silent() -> Self {
+		let port = Self::default();
+		port.device().silent = true;
+		port
+	}
+
+	/// A port that takes all writes, but reads return no bytes like a closed port.
+	pub(crate) fn closed() -> Self {
+		let port = Self::default();
+		port.device().empty_reads = usize::MAX;
+		port
+	}
+
+	/// The next `reads` return no bytes, like during a momentary stall.
+	pub(crate) fn stall(&self, reads: usize) {
+		self.device().empty_reads = reads;
+	}
+
+	/// All bytes written to the port so far.
+	pub(crate) fn written(&self) -> Vec<u8> {
+		self.device().written.clone()
+	}
+
+	/// How many packets the bytes were written in.
+	pub(crate) fn packets(&self) -> usize {
+		self.device().packets
+	}
+
+	/// The LEDs of every update the device acknowledged.
+	pub(crate) fn frames(&self) -> Vec<Vec<u8>> {
+		self.device().frames.clone()
+	}
+
+	fn device(&self) -> MutexGuard<'_, FakeDevice> {
+		self.device.lock().unwrap()
+	}
+
+	/// Moves pending responses into `buf`, `None` if there are none.
+	fn read_responses(&self, buf: &mut [u8]) -> Option<usize> {
+		let mut device = self.device();
+		if device.empty_reads > 0 {
+			device.empty_reads -= 1;
+			return Some(0);
+		}
+		if device.responses.is_empty() {
+			return None;
+		}
+
+		let len = buf.len().min(device.responses.len());
+		for (byte, response) in buf.iter_mut().zip(device.responses.drain(..len)) {
+			*byte = response;
+		}
+		Some(len)
+	}
+
+	/// Takes at most one USB packet like the device, returns how many bytes it took.
+	fn receive(&self, buf: &[u8]) -> usize {
+		let len = buf.len().min(PACKET_LEN);
+		self.device().receive(&buf[..len]);
+		len
+	}
+}
+
+impl FakeDevice {
+	fn receive(&mut self, buf: &[u8]) {
+		self.written.extend_from_slice(buf);
+		self.packets += 1;
+		if self.silent {
+			return;
+		}
+
+		if self.resync.push(buf) {
+			self.received.clear();
+			self.command = None;
+			self.responses.clear();
+			self.responses.extend(DEVICE_RESYNC_MESSAGE);
+			return;
+		}
+
+		self.received.extend_from_slice(buf);
+		while self.step() {}
+	}
+
+	/// Handles the received bytes, returns `false` once more bytes are needed.
+	fn step(&mut self) -> bool {
+		let command = match self.command {
+			Some(command) => command,
+			None => match parse_command(&self.received) {
+				ParseStep::NeedMore => return false,
+				ParseStep::Command(command) if !command.is_query() => {
+					self.responses.extend(DEVICE_PARTIAL_MESSAGE);
+					self.command = Some(command);
+					command
+				}
+				ParseStep::Command(_) | ParseStep::Error => {
+					self.responses.extend(DEVICE_ERROR_MESSAGE);
+					self.received
+						.drain(..MESSAGE_TYPE_LEN.min(self.received.len()));
+					return true;
+				}
+			},
+		};
+
+		let data = &self.received[MESSAGE_TYPE_LEN..];
+		let len = match parse_data(command, data, self.strips * self.leds) {
+			DataStep::NeedMore => return false,
+			DataStep::Error => {
+				self.responses.extend(DEVICE_ERROR_MESSAGE);
+				0
+			}
+			DataStep::Complete(data) => {
+				let value = || u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+				match command {
+					Command::SetStrips => self.strips = value(),
+					Command::SetLeds => self.leds = value(),
+					Command::Update => self.frames.push(data.to_vec()),
+					_ => {}
+				}
+
+				self.responses.extend(DEVICE_OK_MESSAGE);
+				data.len()
+			}
+		};
+
+		self.received.drain(..MESSAGE_TYPE_LEN + len);
+		self.command = None;
+		true
+	}
+}
+
+impl Read for FakePort {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.read_responses(buf)
+			.ok_or_else(|| io::ErrorKind::TimedOut.into())
+	}
+}
+
+impl Write for FakePort {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		Ok(self.receive(buf))
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Reads stay pending while there is no response, the timeouts of the controller end them.
+#[cfg(feature = "tokio")]
+impl AsyncRead for FakePort {
+	fn poll_read(
+		self: Pin<&mut Self>,
+		_: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		let Some(len) = self.read_responses(buf.initialize_unfilled()) else {
+			return Poll::Pending;
+		};
+		buf.advance(len);
+		Poll::Ready(Ok(()))
+	}
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncWrite for FakePort {
+	fn poll_write(
+		self: Pin<&mut Self>,
+		_: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		Poll::Ready(Ok(self.receive(buf)))
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl SerialPort for FakePort {
+	fn name(&self) -> Option<String> {
+		Some("fake".to_string())
+	}
+
+	fn baud_rate(&self) -> serialport::Result<u32> {
+		Ok(921_600)
+	}
+
+	fn data_bits(&self) -> serialport::Result<DataBits> {
+		Ok(DataBits::Eight)
+	}
+
+	fn flow_control(&self) -> serialport::Result<FlowControl> {
+		Ok(FlowControl::None)
+	}
+
+	fn parity(&self) -> serialport::Result<Parity> {
+		Ok(Parity::None)
+	}
+
+	fn stop_bits(&self) -> serialport::Result<StopBits> {
+		Ok(StopBits::One)
+	}
+
+	fn timeout(&self) -> Duration {
+		Duration::ZERO
+	}
+
+	fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn set_flow_control(&mut self, _: FlowControl) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn set_parity(&mut self, _: Parity) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn set_timeout(&mut self, _: Duration) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+		Ok(true)
+	}
+
+	fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+		Ok(true)
+	}
+
+	fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+		Ok(false)
+	}
+
+	fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+		Ok(true)
+	}
+
+	fn bytes_to_read(&self) -> serialport::Result<u32> {
+		Ok(self.device().responses.len() as u32)
+	}
+
+	fn bytes_to_write(&self) -> serialport::Result<u32> {
+		Ok(0)
+	}
+
+	fn clear(&self, buffer: ClearBuffer) -> serialport::Result<()> {
+		if matches!(buffer, ClearBuffer::Input | ClearBuffer::All) {
+			self.device().responses.clear();
+		}
+		Ok(())
+	}
+
+	fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+		Ok(Box::new(self.clone()))
+	}
+
+	fn set_break(&self) -> serialport::Result<()> {
+		Ok(())
+	}
+
+	fn clear_break(&self) -> serialport::Result<()> {
+		Ok(())
+	}
+}
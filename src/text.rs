@@ -0,0 +1,303 @@
+use std::{fmt, time::Duration};
+
+use crate::{Animation, Frame, Layout, BYTES_PER_LED, RGB};
+
+/// The width of every glyph of the built-in font.
+pub const GLYPH_WIDTH: usize = 5;
+/// The height of every glyph of the built-in font.
+pub const GLYPH_HEIGHT: usize = 7;
+/// How far the position moves for every character, one column is left empty between them.
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+
+/// The first character in [`FONT`].
+const FIRST_CHAR: u8 = b' ';
+
+/// The printable ASCII characters from `' '` to `'~'`, every glyph is stored as columns from left
+/// to right with the top row in the lowest bit.
+#[rustfmt::skip]
+const FONT: [[u8; GLYPH_WIDTH]; 95] = [
+	[0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+	[0x00, 0x00, 0x5f, 0x00, 0x00], // !
+	[0x00, 0x07, 0x00, 0x07, 0x00], // "
+	[0x14, 0x7f, 0x14, 0x7f, 0x14], // #
+	[0x24, 0x2a, 0x7f, 0x2a, 0x12], // $
+	[0x23, 0x13, 0x08, 0x64, 0x62], // %
+	[0x36, 0x49, 0x55, 0x22, 0x50], // &
+	[0x00, 0x05, 0x03, 0x00, 0x00], // '
+	[0x00, 0x1c, 0x22, 0x41, 0x00], // (
+	[0x00, 0x41, 0x22, 0x1c, 0x00], // )
+	[0x08, 0x2a, 0x1c, 0x2a, 0x08], // *
+	[0x08, 0x08, 0x3e, 0x08, 0x08], // +
+	[0x00, 0x50, 0x30, 0x00, 0x00], // ,
+	[0x08, 0x08, 0x08, 0x08, 0x08], // -
+	[0x00, 0x60, 0x60, 0x00, 0x00], // .
+	[0x20, 0x10, 0x08, 0x04, 0x02], // /
+	[0x3e, 0x51, 0x49, 0x45, 0x3e], // 0
+	[0x00, 0x42, 0x7f, 0x40, 0x00], // 1
+	[0x42, 0x61, 0x51, 0x49, 0x46], // 2
+	[0x21, 0x41, 0x45, 0x4b, 0x31], // 3
+	[0x18, 0x14, 0x12, 0x7f, 0x10], // 4
+	[0x27, 0x45, 0x45, 0x45, 0x39], // 5
+	[0x3c, 0x4a, 0x49, 0x49, 0x30], // 6
+	[0x01, 0x71, 0x09, 0x05, 0x03], // 7
+	[0x36, 0x49, 0x49, 0x49, 0x36], // 8
+	[0x06, 0x49, 0x49, 0x29, 0x1e], // 9
+	[0x00, 0x36, 0x36, 0x00, 0x00], // :
+	[0x00, 0x56, 0x36, 0x00, 0x00], // ;
+	[0x08, 0x14, 0x22, 0x41, 0x00], // <
+	[0x14, 0x14, 0x14, 0x14, 0x14], // =
+	[0x00, 0x41, 0x22, 0x14, 0x08], // >
+	[0x02, 0x01, 0x51, 0x09, 0x06], // ?
+	[0x32, 0x49, 0x79, 0x41, 0x3e], // @
+	[0x7e, 0x11, 0x11, 0x11, 0x7e], // A
+	[0x7f, 0x49, 0x49, 0x49, 0x36], // B
+	[0x3e, 0x41, 0x41, 0x41, 0x22], // C
+	[0x7f, 0x41, 0x41, 0x22, 0x1c], // D
+	[0x7f, 0x49, 0x49, 0x49, 0x41], // E
+	[0x7f, 0x09, 0x09, 0x09, 0x01], // F
+	[0x3e, 0x41, 0x49, 0x49, 0x7a], // G
+	[0x7f, 0x08, 0x08, 0x08, 0x7f], // H
+	[0x00, 0x41, 0x7f, 0x41, 0x00], // I
+	[0x20, 0x40, 0x41, 0x3f, 0x01], // J
+	[0x7f, 0x08, 0x14, 0x22, 0x41], // K
+	[0x7f, 0x40, 0x40, 0x40, 0x40], // L
+	[0x7f, 0x02, 0x0c, 0x02, 0x7f], // M
+	[0x7f, 0x04, 0x08, 0x10, 0x7f], // N
+	[0x3e, 0x41, 0x41, 0x41, 0x3e], // O
+	[0x7f, 0x09, 0x09, 0x09, 0x06], // P
+	[0x3e, 0x41, 0x51, 0x21, 0x5e], // Q
+	[0x7f, 0x09, 0x19, 0x29, 0x46], // R
+	[0x46, 0x49, 0x49, 0x49, 0x31], // S
+	[0x01, 0x01, 0x7f, 0x01, 0x01], // T
+	[0x3f, 0x40, 0x40, 0x40, 0x3f], // U
+	[0x1f, 0x20, 0x40, 0x20, 0x1f], // V
+	[0x3f, 0x40, 0x38, 0x40, 0x3f], // W
+	[0x63, 0x14, 0x08, 0x14, 0x63], // X
+	[0x07, 0x08, 0x70, 0x08, 0x07], // Y
+	[0x61, 0x51, 0x49, 0x45, 0x43], // Z
+	[0x00, 0x7f, 0x41, 0x41, 0x00], // [
+	[0x02, 0x04, 0x08, 0x10, 0x20], // \
+	[0x00, 0x41, 0x41, 0x7f, 0x00], // ]
+	[0x04, 0x02, 0x01, 0x02, 0x04], // ^
+	[0x40, 0x40, 0x40, 0x40, 0x40], // _
+	[0x00, 0x01, 0x02, 0x04, 0x00], // `
+	[0x20, 0x54, 0x54, 0x54, 0x78], // a
+	[0x7f, 0x48, 0x44, 0x44, 0x38], // b
+	[0x38, 0x44, 0x44, 0x44, 0x20], // c
+	[0x38, 0x44, 0x44, 0x48, 0x7f], // d
+	[0x38, 0x54, 0x54, 0x54, 0x18], // e
+	[0x08, 0x7e, 0x09, 0x01, 0x02], // f
+	[0x0c, 0x52, 0x52, 0x52, 0x3e], // g
+	[0x7f, 0x08, 0x04, 0x04, 0x78], // h
+	[0x00, 0x44, 0x7d, 0x40, 0x00], // i
+	[0x20, 0x40, 0x44, 0x3d, 0x00], // j
+	[0x7f, 0x10, 0x28, 0x44, 0x00], // k
+	[0x00, 0x41, 0x7f, 0x40, 0x00], // l
+	[0x7c, 0x04, 0x18, 0x04, 0x78], // m
+	[0x7c, 0x08, 0x04, 0x04, 0x78], // n
+	[0x38, 0x44, 0x44, 0x44, 0x38], // o
+	[0x7c, 0x14, 0x14, 0x14, 0x08], // p
+	[0x08, 0x14, 0x14, 0x18, 0x7c], // q
+	[0x7c, 0x08, 0x04, 0x04, 0x08], // r
+	[0x48, 0x54, 0x54, 0x54, 0x20], // s
+	[0x04, 0x3f, 0x44, 0x40, 0x20], // t
+	[0x3c, 0x40, 0x40, 0x20, 0x7c], // u
+	[0x1c, 0x20, 0x40, 0x20, 0x1c], // v
+	[0x3c, 0x40, 0x30, 0x40, 0x3c], // w
+	[0x44, 0x28, 0x10, 0x28, 0x44], // x
+	[0x0c, 0x50, 0x50, 0x50, 0x3c], // y
+	[0x44, 0x64, 0x54, 0x4c, 0x44], // z
+	[0x00, 0x08, 0x36, 0x41, 0x00], // {
+	[0x00, 0x00, 0x7f, 0x00, 0x00], // |
+	[0x00, 0x41, 0x36, 0x08, 0x00], // }
+	[0x08, 0x04, 0x08, 0x10, 0x08], // ~
+];
+
+/// The glyph for `c`, characters the font doesn't have are drawn as `?`.
+fn glyph(c: char) -> &'static [u8; GLYPH_WIDTH] {
+	let idx = u8::try_from(c)
+		.ok()
+		.and_then(|c| c.checked_sub(FIRST_CHAR))
+		.filter(|&idx| (idx as usize) < FONT.len())
+		.unwrap_or(b'?' - FIRST_CHAR);
+
+	&FONT[idx as usize]
+}
+
+/// Draws text with a built-in 5x7 font onto a matrix panel.
+///
+/// Positions are the top left corner of the text and can be outside of the panel, everything
+/// outside is clipped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextRenderer {
+	layout: Layout,
+}
+
+impl TextRenderer {
+	pub fn new(layout: Layout) -> Self {
+		Self { layout }
+	}
+
+	pub fn layout(&self) -> Layout {
+		self.layout
+	}
+
+	/// The amount of columns `text` takes up, without the empty column after the last character.
+	pub fn text_width(text: &str) -> usize {
+		(text.chars().count() * ADVANCE).saturating_sub(1)
+	}
+
+	/// Draws `text` at `x`, `y`, only the LEDs of the glyphs are changed.
+	///
+	/// Returns the position for the next character.
+	pub fn draw_text(
+		&self,
+		frame: &mut Frame,
+		text: &str,
+		x: isize,
+		y: isize,
+		color: RGB,
+	) -> isize {
+		text.chars().fold(x, |x, c| {
+			self.draw_char(frame, c, x, y, color);
+			x + ADVANCE as isize
+		})
+	}
+
+	/// Draws a single character at `x`, `y`.
+	pub fn draw_char(&self, frame: &mut Frame, c: char, x: isize, y: isize, color: RGB) {
+		let color: [u8; BYTES_PER_LED] = color.into();
+
+		for (col, bits) in glyph(c).iter().enumerate() {
+			for row in 0..GLYPH_HEIGHT {
+				if bits & (1 << row) != 0 {
+					self.set(frame, x + col as isize, y + row as isize, color);
+				}
+			}
+		}
+	}
+
+	/// Turns off every LED of the panel.
+	pub fn clear(&self, frame: &mut Frame) {
+		for y in 0..self.layout.height {
+			for x in 0..self.layout.width {
+				self.set(frame, x as isize, y as isize, [0; BYTES_PER_LED]);
+			}
+		}
+	}
+
+	/// A writer that draws everything formatted into it, starting at `x`, `y`.
+	///
+	/// ```no_run
+	/// # use std::fmt::Write;
+	/// # use serial_ws2812::{Frame, Layout, TextRenderer, RGB};
+	/// # fn draw(frame: &mut Frame, fps: f32) {
+	/// let text = TextRenderer::new(Layout::serpentine(32, 8));
+	/// write!(text.writer(frame, 0, 0, RGB::new(255, 0, 0)), "{fps:.0} fps").unwrap();
+	/// # }
+	/// ```
+	pub fn writer<'a>(
+		&self,
+		frame: &'a mut Frame,
+		x: isize,
+		y: isize,
+		color: RGB,
+	) -> TextWriter<'a> {
+		TextWriter {
+			renderer: *self,
+			frame,
+			x,
+			y,
+			color,
+		}
+	}
+
+	/// An animation that scrolls `text` from the right edge of the panel to the left at `y`, with
+	/// `speed` columns per second.
+	///
+	/// The animation is finished once the text has left the panel.
+	pub fn scroll_text(
+		&self,
+		text: impl Into<String>,
+		y: isize,
+		color: RGB,
+		speed: f32,
+	) -> ScrollText {
+		ScrollText {
+			renderer: *self,
+			text: text.into(),
+			y,
+			color,
+			speed,
+			finished: false,
+		}
+	}
+
+	/// Sets the LED at `x`, `y` if it's on the panel and in the frame.
+	fn set(&self, frame: &mut Frame, x: isize, y: isize, color: [u8; BYTES_PER_LED]) {
+		let (Ok(x), Ok(y)) = (usize::try_from(x), usize::try_from(y)) else {
+			return;
+		};
+		if x >= self.layout.width || y >= self.layout.height {
+			return;
+		}
+
+		let idx = self.layout.index(x, y) * BYTES_PER_LED;
+		if let Some(led) = frame.as_bytes_mut().get_mut(idx..idx + BYTES_PER_LED) {
+			led.copy_from_slice(&color);
+		}
+	}
+}
+
+/// Draws formatted text onto a frame, see [`TextRenderer::writer`].
+pub struct TextWriter<'a> {
+	renderer: TextRenderer,
+	frame:    &'a mut Frame,
+	x:        isize,
+	y:        isize,
+	color:    RGB,
+}
+
+impl TextWriter<'_> {
+	/// The position the next character is drawn at.
+	pub fn position(&self) -> (isize, isize) {
+		(self.x, self.y)
+	}
+}
+
+impl fmt::Write for TextWriter<'_> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		self.x = self
+			.renderer
+			.draw_text(self.frame, s, self.x, self.y, self.color);
+		Ok(())
+	}
+}
+
+/// Scrolls text across a matrix panel, see [`TextRenderer::scroll_text`].
+#[derive(Clone, Debug)]
+pub struct ScrollText {
+	renderer: TextRenderer,
+	text:     String,
+	y:        isize,
+	color:    RGB,
+	speed:    f32,
+	finished: bool,
+}
+
+impl Animation for ScrollText {
+	fn render(&mut self, frame: &mut Frame, t: Duration) {
+		let offset = (t.as_secs_f32() * self.speed) as isize;
+		let x = self.renderer.layout.width as isize - offset;
+
+		self.renderer.clear(frame);
+		self.renderer
+			.draw_text(frame, &self.text, x, self.y, self.color);
+
+		self.finished = x + TextRenderer::text_width(&self.text) as isize <= 0;
+	}
+
+	fn is_finished(&self) -> bool {
+		self.finished
+	}
+}
@@ -0,0 +1,124 @@
+//! Receives the WLED UDP realtime protocol and forwards it to the device.
+//!
+//! Supports the DRGB and DNRGB packets, every packet is a byte for the protocol, a timeout byte and
+//! then the colors. DNRGB additionally has the index of the first LED as big endian `u16` before
+//! the colors. The timeout is ignored, the device keeps showing the last frame.
+
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+use crate::{Frame, Result, SerialWs2812, BYTES_PER_LED};
+
+/// The port WLED listens on for realtime data.
+pub const UDP_REALTIME_PORT: u16 = 21324;
+
+const PROTOCOL_DRGB: u8 = 2;
+const PROTOCOL_DNRGB: u8 = 4;
+
+/// Maps the LED index of a packet to a strip and LED, `None` drops the color.
+pub type IndexMapping = Box<dyn Fn(usize) -> Option<(usize, usize)> + Send>;
+
+/// Listens for WLED realtime packets and sends the received colors to the device.
+pub struct UdpRealtime {
+	controller: SerialWs2812,
+	socket:     UdpSocket,
+	frame:      Frame,
+	mapping:    Option<IndexMapping>,
+}
+
+impl UdpRealtime {
+	/// Binds to the WLED realtime port on all interfaces.
+	///
+	/// The LED indices of the packets map to the strip-major frame, the first LEDs of a packet go to
+	/// the first strip and continue on the next one.
+	pub fn new(controller: SerialWs2812) -> Result<Self> {
+		Self::bind(controller, UDP_REALTIME_PORT)
+	}
+
+	/// Binds to a custom port on all interfaces.
+	pub fn bind(controller: SerialWs2812, port: u16) -> Result<Self> {
+		let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))?;
+		let frame = Frame::new(controller.config());
+
+		Ok(Self {
+			controller,
+			socket,
+			frame,
+			mapping: None,
+		})
+	}
+
+	/// Uses a custom mapping from the LED index in the packets to a strip and LED.
+	pub fn with_mapping(
+		mut self,
+		mapping: impl Fn(usize) -> Option<(usize, usize)> + Send + 'static,
+	) -> Self {
+		self.mapping = Some(Box::new(mapping));
+		self
+	}
+
+	/// Receives and forwards packets until an error occurs.
+	pub fn run(&mut self) -> Result<()> {
+		loop {
+			self.poll()?;
+		}
+	}
+
+	/// Receives a single packet, every valid packet is sent to the device right away.
+	pub fn poll(&mut self) -> Result<()> {
+		let mut packet = [0u8; 1472];
+		let len = self.socket.recv(&mut packet)?;
+
+		let Some((start, colors)) = parse_packet(&packet[..len]) else {
+			return Ok(());
+		};
+
+		self.apply(start, colors);
+		self.controller.send_frame(&self.frame)?;
+
+		Ok(())
+	}
+
+	/// Gives back the controller.
+	pub fn into_inner(self) -> SerialWs2812 {
+		self.controller
+	}
+
+	fn apply(&mut self, start: usize, colors: &[u8]) {
+		for (idx, color) in (start..).zip(colors.chunks_exact(BYTES_PER_LED)) {
+			let color = [color[0], color[1], color[2]];
+
+			match &self.mapping {
+				Some(mapping) => {
+					let Some((strip, led)) = mapping(idx) else {
+						continue;
+					};
+					if strip < self.frame.strips() && led < self.frame.strip_lengths()[strip] {
+						self.frame.set_pixel(strip, led, color);
+					}
+				}
+				None => {
+					let offset = idx * BYTES_PER_LED;
+					let Some(bytes) = self
+						.frame
+						.as_bytes_mut()
+						.get_mut(offset..offset + BYTES_PER_LED)
+					else {
+						break;
+					};
+					bytes.copy_from_slice(&color);
+				}
+			}
+		}
+	}
+}
+
+/// Returns the index of the first LED and the colors of a DRGB or DNRGB packet.
+fn parse_packet(packet: &[u8]) -> Option<(usize, &[u8])> {
+	match packet {
+		[PROTOCOL_DRGB, _timeout, colors @ ..] => Some((0, colors)),
+		[PROTOCOL_DNRGB, _timeout, hi, lo, colors @ ..] => {
+			Some((u16::from_be_bytes([*hi, *lo]) as usize, colors))
+		}
+		_ => None,
+	}
+}